@@ -0,0 +1,92 @@
+//! Compares dispatch throughput before and after `Busstop::freeze`,
+//! with and without middlewares and a dead letter sink
+//!
+//! Run with `cargo bench --bench dispatch`
+use std::{
+    sync::Arc,
+    time::{Duration, Instant},
+};
+
+use busstop::{Busstop, MemoryDeadLetters};
+
+const TASKS: usize = 8;
+const DISPATCHES_PER_TASK: usize = 50_000;
+const MIDDLEWARES: usize = 4;
+
+struct Ping;
+
+struct Sum(u64, u64);
+
+async fn run(bus: Arc<Busstop>) -> Duration {
+    let started = Instant::now();
+    let tasks = (0..TASKS)
+        .map(|_| {
+            let bus = Arc::clone(&bus);
+            tokio::spawn(async move {
+                for n in 0..DISPATCHES_PER_TASK as u64 {
+                    bus.dispatch_command(Ping).await;
+                    bus.dispatch_query(Sum(n, 1)).await;
+                }
+            })
+        })
+        .collect::<Vec<_>>();
+
+    for task in tasks {
+        task.await.unwrap();
+    }
+
+    started.elapsed()
+}
+
+fn report(label: &str, elapsed: Duration) {
+    let dispatches = (TASKS * DISPATCHES_PER_TASK * 2) as f64;
+    println!(
+        "{label:>11}: {:>8.2?} ({:.0} dispatches/s)",
+        elapsed,
+        dispatches / elapsed.as_secs_f64()
+    );
+}
+
+async fn bus_with(middlewares: usize) -> Arc<Busstop> {
+    let bus = Arc::new(Busstop::new());
+    bus.register_command_fn::<Ping, _>(|_: Ping| async {})
+        .await
+        .register_query_fn::<Sum, _>(|q: &Sum| {
+            let sum = q.0 + q.1;
+            async move { sum }
+        })
+        .await;
+
+    for _ in 0..middlewares {
+        bus.register_command_middleware::<Ping, _>(|c, n| Box::pin(n.call(c)))
+            .await
+            .register_query_middleware::<Sum, _>(|q, n| Box::pin(n.call(q)))
+            .await;
+    }
+
+    bus
+}
+
+#[tokio::main(flavor = "multi_thread")]
+async fn main() {
+    let bus = bus_with(0).await;
+
+    // Warm up
+    run(Arc::clone(&bus)).await;
+
+    report("unfrozen", run(Arc::clone(&bus)).await);
+    bus.freeze().await;
+    report("frozen", run(bus).await);
+
+    let bus = bus_with(MIDDLEWARES).await;
+    report("middleware", run(Arc::clone(&bus)).await);
+    bus.freeze().await;
+    report("frozen+mw", run(bus).await);
+
+    // The commands succeed, so the sink is checked but never stores a letter
+    let bus = bus_with(0).await;
+    bus.set_dead_letter_sink(MemoryDeadLetters::new()).await;
+    report("sink", run(Arc::clone(&bus)).await);
+    bus.freeze().await;
+    report("frozen+sink", run(bus).await);
+}
@@ -0,0 +1,48 @@
+use busstop::{Busstop, DispatchableCommand, DispatchableQuery};
+use tracing::Level;
+
+#[tokio::main]
+async fn main() {
+    // For logging purposes
+    tracing_subscriber::fmt()
+        .with_max_level(Level::DEBUG)
+        .try_init()
+        .expect("could not setup tracing");
+
+    let bus = Busstop::instance();
+
+    // 1. Register a closure as the handler for "CreateUser" command.
+    //    The command is passed to the closure by value
+    bus.register_command_fn::<CreateUser, _>(|cmd: CreateUser| async move {
+        println!("handling create user: {:?}", cmd.email);
+    })
+    .await;
+
+    // 2. Register a closure as the handler for "UserCount" query.
+    //    The value returned is the query's value
+    bus.register_query_fn::<UserCount, _>(|_: &UserCount| async move { 1_usize })
+        .await;
+
+    // 3. Dispatch the command and the query
+    CreateUser {
+        email: "james@james.com".to_string(),
+    }
+    .dispatch_command()
+    .await;
+
+    println!(
+        "Total users: {:?}",
+        UserCount.dispatch_query().await.value::<usize>()
+    );
+}
+
+#[derive(Debug)]
+struct CreateUser {
+    pub email: String,
+}
+
+impl DispatchableCommand for CreateUser {}
+
+struct UserCount;
+
+impl DispatchableQuery for UserCount {}
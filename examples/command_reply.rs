@@ -0,0 +1,45 @@
+use std::sync::atomic::{AtomicU64, Ordering};
+
+use busstop::{Busstop, DispatchableCommand, DispatchableCommandWithReply};
+use tracing::Level;
+
+static NEXT_ID: AtomicU64 = AtomicU64::new(1);
+
+#[tokio::main]
+async fn main() {
+    // For logging purposes
+    tracing_subscriber::fmt()
+        .with_max_level(Level::DEBUG)
+        .try_init()
+        .expect("could not setup tracing");
+
+    let bus = Busstop::instance();
+
+    // 1. Register a closure as the handler for "CreateUser" command.
+    //    The value returned is the command's reply and must be a `u64`
+    bus.register_command_fn_with_reply::<CreateUser, _>(|cmd: CreateUser| async move {
+        println!("handling create user: {:?}", cmd.email);
+        NEXT_ID.fetch_add(1, Ordering::Relaxed)
+    })
+    .await;
+
+    // 2. Dispatch the command and get the id of the new user
+    let id = CreateUser {
+        email: "james@james.com".to_string(),
+    }
+    .dispatch_command_with_reply()
+    .await;
+
+    println!("Created user: {:?}", id);
+}
+
+#[derive(Debug)]
+struct CreateUser {
+    pub email: String,
+}
+
+impl DispatchableCommand for CreateUser {}
+
+impl DispatchableCommandWithReply for CreateUser {
+    type Reply = u64;
+}
@@ -0,0 +1,64 @@
+use busstop::{Busstop, JsonCodec, SerializableMessage};
+use serde::{Deserialize, Serialize};
+use tracing::Level;
+
+#[tokio::main]
+async fn main() {
+    // For logging purposes
+    tracing_subscriber::fmt()
+        .with_max_level(Level::DEBUG)
+        .try_init()
+        .expect("could not setup tracing");
+
+    let bus = Busstop::instance();
+
+    // 1. Register the handlers as usual
+    bus.register_command_fn::<CreateUser, _>(|cmd: CreateUser| async move {
+        println!("handling create user: {:?}", cmd.email);
+    })
+    .await;
+    bus.register_query_fn::<SumOfQuery, _>(|q: &SumOfQuery| {
+        let sum: i32 = q.numbers.iter().sum();
+        async move { sum }
+    })
+    .await;
+
+    // 2. Allow the messages to be dispatched by name
+    bus.register_dynamic_command::<CreateUser>().await;
+    bus.register_dynamic_query::<SumOfQuery, i32>().await;
+
+    // 3. Dispatch the messages from their JSON representation
+    let handled = bus
+        .dispatch_command_dynamic("create_user", br#"{"email":"james@james.com"}"#, &JsonCodec)
+        .await;
+    println!("create user handled: {:?}", handled);
+
+    let value = bus
+        .dispatch_query_dynamic("sum_of", br#"{"numbers":[2,4,6,8]}"#, &JsonCodec)
+        .await
+        .expect("query to be dispatched")
+        .map(String::from_utf8);
+    println!("sum of: {:?}", value);
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+struct CreateUser {
+    pub email: String,
+}
+
+impl SerializableMessage for CreateUser {
+    fn message_name() -> &'static str {
+        "create_user"
+    }
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+struct SumOfQuery {
+    pub numbers: Vec<i32>,
+}
+
+impl SerializableMessage for SumOfQuery {
+    fn message_name() -> &'static str {
+        "sum_of"
+    }
+}
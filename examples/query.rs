@@ -46,7 +46,7 @@ impl QueryHandler for HandleSumOfQuery {
 
         let sum = if let Some(subject) = query {
             tracing::info!("summing up: {:?}", subject.numbers);
-            subject.numbers.iter().fold(0, |sum, n| sum + n)
+            subject.numbers.iter().sum::<i32>()
         } else {
             0
         };
@@ -0,0 +1,111 @@
+use busstop::{Busstop, DispatchableQuery, DispatchedQuery, QueryHandler};
+use tracing::Level;
+
+#[tokio::main]
+async fn main() {
+    // For logging purposes
+    tracing_subscriber::fmt()
+        .with_max_level(Level::DEBUG)
+        .try_init()
+        .expect("could not setup tracing");
+
+    // 1. Each handler answers the operations it knows and declines the others.
+    //    Declined queries are passed to the next handler, in the order the
+    //    handlers were added
+    let bus = Busstop::instance();
+    bus.add_query_handler::<MathQuery>(AdditionHandler)
+        .await
+        .add_query_handler::<MathQuery>(SubtractionHandler)
+        .await
+        .add_query_handler::<MathQuery>(DivisionHandler)
+        .await;
+
+    // -- Below are various query calls
+
+    println!(
+        "Response for: 2 + 2 = {:?}",
+        MathQuery::Add(2, 2).dispatch_query().await.value::<usize>()
+    );
+    println!(
+        "Response for: 88 - 12 = {:?}",
+        MathQuery::Subtract(88, 12)
+            .dispatch_query()
+            .await
+            .value::<usize>()
+    );
+    println!(
+        "Response for: 8701 ➗ 6 = {:?}",
+        MathQuery::Divide(8701, 6)
+            .dispatch_query()
+            .await
+            .value::<usize>()
+    );
+
+    // 2. Every handler declines division by zero and multiplication,
+    //    so the query is not handled
+    let result = MathQuery::Divide(600, 0).dispatch_query().await;
+    println!(
+        "Response for: 600 ➗ 0 = {:?}, handled: {}",
+        result.value::<usize>(),
+        result.handled()
+    );
+
+    let result = MathQuery::Multiply(45, 8).dispatch_query().await;
+    println!(
+        "Response for: 45 x 8 = {:?}, handled: {}",
+        result.value::<usize>(),
+        result.handled()
+    );
+}
+
+#[allow(dead_code)]
+enum MathQuery {
+    Add(usize, usize),
+    Subtract(usize, usize),
+    Multiply(usize, usize),
+    Divide(usize, usize),
+}
+
+impl DispatchableQuery for MathQuery {}
+
+struct AdditionHandler;
+
+#[busstop::async_trait]
+impl QueryHandler for AdditionHandler {
+    async fn handle_query(&self, mut query: DispatchedQuery) -> DispatchedQuery {
+        match query.the_query::<MathQuery>() {
+            Some(MathQuery::Add(n1, n2)) => query.set_value(n1 + n2),
+            _ => query.decline(),
+        }
+
+        query
+    }
+}
+
+struct SubtractionHandler;
+
+#[busstop::async_trait]
+impl QueryHandler for SubtractionHandler {
+    async fn handle_query(&self, mut query: DispatchedQuery) -> DispatchedQuery {
+        match query.the_query::<MathQuery>() {
+            Some(MathQuery::Subtract(n1, n2)) => query.set_value(n1 - n2),
+            _ => query.decline(),
+        }
+
+        query
+    }
+}
+
+struct DivisionHandler;
+
+#[busstop::async_trait]
+impl QueryHandler for DivisionHandler {
+    async fn handle_query(&self, mut query: DispatchedQuery) -> DispatchedQuery {
+        match query.the_query::<MathQuery>() {
+            Some(MathQuery::Divide(n1, n2)) if *n2 != 0 => query.set_value(n1 / n2),
+            _ => query.decline(),
+        }
+
+        query
+    }
+}
@@ -0,0 +1,274 @@
+use std::{
+    any::{Any, TypeId},
+    collections::HashMap,
+    sync::Arc,
+};
+
+use crate::{Busstop, DispatchError, Metadata};
+
+const LOG_TARGET: &str = "bus_stop";
+
+/// The metadata key that holds the principal, the identity of the caller
+///
+/// The HTTP gateway and the envelope transports drop this key from the
+/// metadata sent by the caller. Only the adapter sets it, from what the
+/// caller was authenticated as
+pub const PRINCIPAL_METADATA_KEY: &str = "principal";
+
+/// Decides if a principal may dispatch a message of type `M`
+///
+/// This trait is implemented for every `Fn(Option<&str>, &M) -> bool`
+pub trait Authorize<M>: Send + Sync + 'static {
+    /// Returns `Err` with the reason when the principal is not allowed
+    /// to dispatch the message. The principal is `None` when the
+    /// metadata does not have one
+    fn authorize(&self, principal: Option<&str>, message: &M) -> Result<(), String>;
+}
+
+impl<M, F> Authorize<M> for F
+where
+    F: Fn(Option<&str>, &M) -> bool + Send + Sync + 'static,
+{
+    fn authorize(&self, principal: Option<&str>, message: &M) -> Result<(), String> {
+        if (self)(principal, message) {
+            Ok(())
+        } else {
+            Err(format!(
+                "{} may not dispatch {}",
+                principal.unwrap_or("anonymous"),
+                std::any::type_name::<M>()
+            ))
+        }
+    }
+}
+
+/// Removes the principal a caller put in the metadata it sent
+#[cfg(feature = "serde")]
+pub(crate) fn untrusted(mut metadata: Metadata) -> Metadata {
+    if let Some(principal) = metadata.remove(PRINCIPAL_METADATA_KEY) {
+        tracing::warn!(target: LOG_TARGET, "dropped the principal {:?} sent by the caller", principal);
+    }
+    metadata
+}
+
+type Policy =
+    Arc<dyn Fn(Option<&str>, &(dyn Any + Send + Sync)) -> Result<(), String> + Send + Sync>;
+
+/// The policies registered per message type, along with the name of the type
+#[derive(Clone, Default)]
+pub(crate) struct Authorization {
+    commands: HashMap<TypeId, (&'static str, Policy)>,
+    queries: HashMap<TypeId, (&'static str, Policy)>,
+    strict: bool,
+}
+
+impl Authorization {
+    pub(crate) fn check_command(
+        &self,
+        name: &str,
+        message: Option<&(dyn Any + Send + Sync)>,
+        metadata: &Metadata,
+    ) -> Result<(), DispatchError> {
+        self.check(
+            policy(&self.commands, name, message),
+            name,
+            message,
+            metadata,
+        )
+    }
+
+    pub(crate) fn check_query(
+        &self,
+        name: &str,
+        message: Option<&(dyn Any + Send + Sync)>,
+        metadata: &Metadata,
+    ) -> Result<(), DispatchError> {
+        self.check(
+            policy(&self.queries, name, message),
+            name,
+            message,
+            metadata,
+        )
+    }
+
+    fn check(
+        &self,
+        policy: Option<&Policy>,
+        name: &str,
+        message: Option<&(dyn Any + Send + Sync)>,
+        metadata: &Metadata,
+    ) -> Result<(), DispatchError> {
+        let principal = metadata.get(PRINCIPAL_METADATA_KEY);
+        let result = match (policy, message) {
+            (Some(policy), Some(message)) => policy(principal, message),
+            (Some(_), None) => Err(format!("{} has already been taken", name)),
+            (None, _) if self.strict => Err(format!("no policy for {}", name)),
+            (None, _) => Ok(()),
+        };
+
+        result.map_err(|reason| {
+            tracing::debug!(target: LOG_TARGET, "{:?} denied for {:?}: {}", name, principal, reason);
+            DispatchError::Forbidden(reason)
+        })
+    }
+}
+
+fn policy<'a>(
+    policies: &'a HashMap<TypeId, (&'static str, Policy)>,
+    name: &str,
+    message: Option<&(dyn Any + Send + Sync)>,
+) -> Option<&'a Policy> {
+    let found = match message {
+        Some(message) => policies.get(&Any::type_id(message)),
+        // Only the name is left of a message that has been taken
+        None => policies
+            .values()
+            .find(|(policy_for, _)| *policy_for == name),
+    };
+    found.map(|(_, policy)| policy)
+}
+
+fn erase<M: Send + Sync + 'static>(policy: impl Authorize<M>) -> Policy {
+    Arc::new(
+        move |principal, message| match message.downcast_ref::<M>() {
+            Some(message) => policy.authorize(principal, message),
+            None => Err(format!("expected {}", std::any::type_name::<M>())),
+        },
+    )
+}
+
+impl Busstop {
+    /// Sets the policy that decides who may dispatch the command.
+    /// Commands that are denied fail with `DispatchError::Forbidden`
+    /// and are not handled
+    pub async fn register_command_policy<C: Send + Sync + 'static>(
+        &self,
+        policy: impl Authorize<C>,
+    ) -> &Self {
+        let name = std::any::type_name::<C>();
+        tracing::debug!(target: LOG_TARGET, "registered policy for command {:?}", name);
+        let policy = erase(policy);
+        self.authorization.rcu(|current| {
+            let mut authorization = Authorization::clone(current);
+            authorization
+                .commands
+                .insert(TypeId::of::<C>(), (name, Arc::clone(&policy)));
+            authorization
+        });
+
+        self
+    }
+
+    /// Sets the policy that decides who may dispatch the query.
+    /// Queries that are denied fail with `DispatchError::Forbidden`
+    /// and are not handled
+    pub async fn register_query_policy<Q: Send + Sync + 'static>(
+        &self,
+        policy: impl Authorize<Q>,
+    ) -> &Self {
+        let name = std::any::type_name::<Q>();
+        tracing::debug!(target: LOG_TARGET, "registered policy for query {:?}", name);
+        let policy = erase(policy);
+        self.authorization.rcu(|current| {
+            let mut authorization = Authorization::clone(current);
+            authorization
+                .queries
+                .insert(TypeId::of::<Q>(), (name, Arc::clone(&policy)));
+            authorization
+        });
+
+        self
+    }
+
+    /// In strict mode, commands and queries without a policy are denied.
+    /// Messages that nothing handles are not denied, they stay unhandled
+    pub async fn set_strict_authorization(&self, strict: bool) -> &Self {
+        self.authorization.rcu(|current| Authorization {
+            strict,
+            ..Authorization::clone(current)
+        });
+        self
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::DispatchedCommand;
+
+    struct DeletePost {
+        author: String,
+    }
+
+    struct ReadPost;
+
+    struct ArchivePost;
+
+    async fn route_as<C: Send + Sync + 'static>(
+        bus: &Busstop,
+        command: C,
+        principal: Option<&str>,
+    ) -> DispatchedCommand {
+        let metadata = principal
+            .map(|p| Metadata::from_iter([(PRINCIPAL_METADATA_KEY, p)]))
+            .unwrap_or_default();
+        bus.route_command(
+            DispatchedCommand::new(Box::new(command), std::any::type_name::<C>())
+                .with_metadata(metadata),
+        )
+        .await
+    }
+
+    #[tokio::test]
+    async fn test_command_policy() {
+        let bus = Busstop::new();
+        bus.register_command_fn::<DeletePost, _>(|_: DeletePost| async {})
+            .await
+            .register_command_policy::<DeletePost>(|principal: Option<&str>, c: &DeletePost| {
+                principal == Some("admin") || principal == Some(c.author.as_str())
+            })
+            .await;
+
+        let post = || DeletePost {
+            author: "ada".to_string(),
+        };
+        assert!(route_as(&bus, post(), Some("ada")).await.succeeded());
+        assert!(route_as(&bus, post(), Some("admin")).await.succeeded());
+
+        let result = route_as(&bus, post(), Some("bob")).await;
+        assert!(!result.handled());
+        assert!(matches!(result.error(), Some(DispatchError::Forbidden(_))));
+        assert!(matches!(
+            route_as(&bus, post(), None).await.error(),
+            Some(DispatchError::Forbidden(_))
+        ));
+    }
+
+    #[tokio::test]
+    async fn test_strict_mode_denies_types_without_policy() {
+        let bus = Busstop::new();
+        bus.register_command_fn::<ReadPost, _>(|_: ReadPost| async {})
+            .await;
+        assert!(bus.dispatch_command(ReadPost).await);
+
+        bus.set_strict_authorization(true).await;
+        let result = route_as(&bus, ReadPost, Some("admin")).await;
+        assert_eq!(
+            result.error(),
+            Some(&DispatchError::Forbidden(format!(
+                "no policy for {}",
+                std::any::type_name::<ReadPost>()
+            )))
+        );
+    }
+
+    #[tokio::test]
+    async fn test_strict_mode_reports_types_without_handler_as_unhandled() {
+        let bus = Busstop::new();
+        bus.set_strict_authorization(true).await;
+
+        let result = route_as(&bus, ArchivePost, Some("admin")).await;
+        assert!(!result.handled());
+        assert!(result.error().is_none());
+    }
+}
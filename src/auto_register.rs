@@ -0,0 +1,146 @@
+//! Handlers collected at link time
+//!
+//! Handlers declared with [`auto_command_handler!`](crate::auto_command_handler) or
+//! [`auto_query_handler!`](crate::auto_query_handler) anywhere in the final binary
+//! are registered when [`Busstop::auto_register`](crate::Busstop::auto_register) is called.
+use futures::future::BoxFuture;
+
+use crate::Busstop;
+
+#[doc(hidden)]
+pub use inventory;
+
+type Register = for<'a> fn(&'a Busstop) -> BoxFuture<'a, ()>;
+
+/// A command handler collected at link time
+pub struct AutoCommandHandler {
+    command: fn() -> &'static str,
+    register: Register,
+}
+
+impl AutoCommandHandler {
+    #[doc(hidden)]
+    pub const fn new(command: fn() -> &'static str, register: Register) -> Self {
+        Self { command, register }
+    }
+
+    /// The type name of the command
+    pub fn command(&self) -> &'static str {
+        (self.command)()
+    }
+}
+
+/// A query handler collected at link time
+pub struct AutoQueryHandler {
+    query: fn() -> &'static str,
+    register: Register,
+}
+
+impl AutoQueryHandler {
+    #[doc(hidden)]
+    pub const fn new(query: fn() -> &'static str, register: Register) -> Self {
+        Self { query, register }
+    }
+
+    /// The type name of the query
+    pub fn query(&self) -> &'static str {
+        (self.query)()
+    }
+}
+
+inventory::collect!(AutoCommandHandler);
+inventory::collect!(AutoQueryHandler);
+
+impl Busstop {
+    /// Registers every handler declared with `auto_command_handler!`
+    /// and `auto_query_handler!`
+    ///
+    /// Like `register_command` and `register_query`, this panics if a
+    /// command or query already has a handler
+    pub async fn auto_register(&self) -> &Self {
+        for entry in inventory::iter::<AutoCommandHandler> {
+            (entry.register)(self).await;
+        }
+
+        for entry in inventory::iter::<AutoQueryHandler> {
+            (entry.register)(self).await;
+        }
+
+        self
+    }
+}
+
+/// Declares the handler for a command. The handler is registered
+/// when `Busstop::auto_register` is called
+///
+/// The handler must implement `Default`
+///
+/// ```rust
+///# #![allow(dead_code)]
+/// use busstop::{CommandHandler, DispatchedCommand};
+///
+/// struct CreateUser;
+///
+/// #[derive(Default)]
+/// struct CreateUserHandler;
+///
+/// #[busstop::async_trait]
+/// impl CommandHandler for CreateUserHandler {
+///     async fn handle_command(&self, dc: DispatchedCommand) -> DispatchedCommand {
+///         dc
+///     }
+/// }
+///
+/// busstop::auto_command_handler!(CreateUser, CreateUserHandler);
+/// ```
+#[macro_export]
+macro_rules! auto_command_handler {
+    ($command:ty, $handler:ty $(,)?) => {
+        const _: () = {
+            fn __busstop_register(
+                bus: &$crate::Busstop,
+            ) -> ::std::pin::Pin<Box<dyn ::std::future::Future<Output = ()> + Send + '_>> {
+                Box::pin(async move {
+                    bus.register_command::<$command>(
+                        <$handler as ::std::default::Default>::default(),
+                    )
+                    .await;
+                })
+            }
+
+            $crate::auto_register::inventory::submit! {
+                $crate::auto_register::AutoCommandHandler::new(
+                    ::std::any::type_name::<$command>,
+                    __busstop_register,
+                )
+            }
+        };
+    };
+}
+
+/// Declares the handler for a query. The handler is registered
+/// when `Busstop::auto_register` is called
+///
+/// The handler must implement `Default`
+#[macro_export]
+macro_rules! auto_query_handler {
+    ($query:ty, $handler:ty $(,)?) => {
+        const _: () = {
+            fn __busstop_register(
+                bus: &$crate::Busstop,
+            ) -> ::std::pin::Pin<Box<dyn ::std::future::Future<Output = ()> + Send + '_>> {
+                Box::pin(async move {
+                    bus.register_query::<$query>(<$handler as ::std::default::Default>::default())
+                        .await;
+                })
+            }
+
+            $crate::auto_register::inventory::submit! {
+                $crate::auto_register::AutoQueryHandler::new(
+                    ::std::any::type_name::<$query>,
+                    __busstop_register,
+                )
+            }
+        };
+    };
+}
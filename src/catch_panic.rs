@@ -0,0 +1,25 @@
+use std::{any::Any, future::Future, panic::AssertUnwindSafe};
+
+use futures::FutureExt;
+
+/// Polls the future, turning a panic into the value returned by `on_panic`.
+/// `on_panic` receives the panic message
+pub(crate) async fn catch_panic<T>(
+    future: impl Future<Output = T>,
+    on_panic: impl FnOnce(String) -> T,
+) -> T {
+    match AssertUnwindSafe(future).catch_unwind().await {
+        Ok(value) => value,
+        Err(payload) => on_panic(panic_message(payload.as_ref())),
+    }
+}
+
+fn panic_message(payload: &(dyn Any + Send)) -> String {
+    if let Some(message) = payload.downcast_ref::<&str>() {
+        message.to_string()
+    } else if let Some(message) = payload.downcast_ref::<String>() {
+        message.clone()
+    } else {
+        "unknown panic".to_string()
+    }
+}
@@ -1,21 +1,31 @@
+mod command_fn_handler;
 mod command_handler;
 mod dispatched_command;
 
 use std::sync::Arc;
 
+use arc_swap::ArcSwap;
+pub use command_fn_handler::{CommandFn, CommandFnHandler};
 pub use command_handler::CommandHandler;
 pub use dispatched_command::DispatchedCommand;
 use futures::future::BoxFuture;
-use simple_middleware::{Manager as MiddlewareManager, Next};
 
-use crate::Busstop;
+use crate::{
+    Busstop, DispatchError,
+    catch_panic::catch_panic,
+    middleware::{Chain, Next},
+};
+
+const LOG_TARGET: &str = "bus_stop";
 
 /// Next middleware to call. Send argument pass to all commands' middlewares
-pub type NextCommandMiddleware = Next<DispatchedCommand, DispatchedCommand>;
+///
+/// Since 0.3.0 this is [`crate::Next`], it used to be `simple_middleware::Next`
+pub type NextCommandMiddleware = Next<DispatchedCommand>;
 
 /// Command middleware type
 pub type CommandMiddleware = Box<
-    dyn FnMut(DispatchedCommand, NextCommandMiddleware) -> BoxFuture<'static, DispatchedCommand>
+    dyn Fn(DispatchedCommand, NextCommandMiddleware) -> BoxFuture<'static, DispatchedCommand>
         + Send
         + Sync,
 >;
@@ -30,15 +40,15 @@ pub trait DispatchableCommand: Send + Sync {
     where
         Self: Sized + 'static,
     {
-        Busstop::instance().dispatch_command(self).await
+        Busstop::current().dispatch_command(self).await
     }
 
     /// Register this handler for this command
     async fn command_handler<H: CommandHandler + Default + 'static>()
     where
-        Self: Sized,
+        Self: Sized + 'static,
     {
-        Busstop::instance()
+        Busstop::current()
             .register_command::<Self>(H::default())
             .await;
     }
@@ -46,15 +56,12 @@ pub trait DispatchableCommand: Send + Sync {
     /// Register a middleware on this dispatchable command
     async fn command_middleware<M: 'static>(middleware: M)
     where
-        Self: Sized,
-        M: FnMut(
-                DispatchedCommand,
-                Next<DispatchedCommand, DispatchedCommand>,
-            ) -> BoxFuture<'static, DispatchedCommand>
+        Self: Sized + 'static,
+        M: Fn(DispatchedCommand, NextCommandMiddleware) -> BoxFuture<'static, DispatchedCommand>
             + Send
             + Sync,
     {
-        Busstop::instance()
+        Busstop::current()
             .register_command_middleware::<Self, M>(middleware)
             .await;
     }
@@ -62,9 +69,9 @@ pub trait DispatchableCommand: Send + Sync {
     /// Register this handler if the command does not have an existing handler
     async fn soft_command_handler<H: CommandHandler + Default + 'static>()
     where
-        Self: Sized,
+        Self: Sized + 'static,
     {
-        let bus = Busstop::instance();
+        let bus = Busstop::current();
         if !bus.command_has_handler::<Self>().await {
             bus.register_command::<Self>(H::default()).await;
         }
@@ -73,43 +80,130 @@ pub trait DispatchableCommand: Send + Sync {
     /// Register the instance as the handler for this command
     async fn register_command_handler<H: CommandHandler + 'static>(handler: H)
     where
-        Self: Sized,
+        Self: Sized + 'static,
     {
-        Busstop::instance().register_command::<Self>(handler).await;
+        Busstop::current().register_command::<Self>(handler).await;
     }
 
     /// Register the instance as the soft handler for this command
     async fn register_soft_command_handler<H: CommandHandler + 'static>(handler: H)
     where
-        Self: Sized,
+        Self: Sized + 'static,
     {
-        let bus = Busstop::instance();
+        let bus = Busstop::current();
         if !bus.command_has_handler::<Self>().await {
             bus.register_command::<Self>(handler).await;
         }
     }
 }
 
+/// A command whose handler returns a reply to the dispatcher, for example
+/// the id of the entity the command created
+///
+/// The handler sets the reply with `DispatchedCommand::set_reply`, a
+/// function registered with `register_command_fn` replies with the value it returns
+///
+/// The reply type lives in its own trait rather than in
+/// `DispatchableCommand`: stable Rust has no associated type defaults, so a
+/// `type Reply` there would have to be set by every existing implementor
+#[async_trait::async_trait]
+pub trait DispatchableCommandWithReply: DispatchableCommand {
+    /// The type of the reply
+    type Reply: Send + Sync + 'static;
+
+    /// Dispatch the command and return the reply set by its handler
+    async fn dispatch_command_with_reply(self) -> Option<Self::Reply>
+    where
+        Self: Sized + 'static,
+    {
+        Busstop::current().dispatch_command_with_reply(self).await
+    }
+}
+
 /// Manages the middlewares for the current command handler
+///
+/// The manager holds an ordered list of handlers. A handler that declines
+/// the command passes it to the next one
+///
+/// When a handler or a middleware panics, the command fails with
+/// `DispatchError::Panicked`. The command and its reply unwind with the
+/// panic, the failed command that is returned holds only the metadata
 pub struct CommandHandlerManager {
     name: String,
-    middleware: MiddlewareManager<DispatchedCommand, DispatchedCommand>,
+    message: &'static str,
+    handlers: Arc<ArcSwap<Vec<Arc<dyn CommandHandler>>>>,
+    middleware: Chain<DispatchedCommand>,
 }
 
 impl CommandHandlerManager {
     /// Create a new instance
     pub async fn new(handler: impl CommandHandler + 'static) -> Self {
-        let handler = Arc::new(Box::new(handler));
+        let name = handler.command_handler_name().to_string();
+        let handlers: Arc<ArcSwap<Vec<Arc<dyn CommandHandler>>>> =
+            Arc::new(ArcSwap::from_pointee(vec![Arc::new(handler)]));
+        let candidates = Arc::clone(&handlers);
+
         Self {
-            name: handler.command_handler_name().to_string(),
-            middleware: MiddlewareManager::last(move |dispatched, _| {
-                let instance = handler.clone();
-                Box::pin(async move { instance.clone().handle_command(dispatched).await })
-            })
-            .await,
+            name,
+            message: "",
+            handlers,
+            middleware: Chain::new(move |mut dispatched: DispatchedCommand| {
+                let candidates = candidates.load_full();
+                Box::pin(async move {
+                    for instance in candidates.iter() {
+                        let name = dispatched.name();
+                        let metadata = dispatched.metadata().clone();
+                        dispatched.declined = false;
+                        dispatched = catch_panic(instance.handle_command(dispatched), |message| {
+                            tracing::error!(target: LOG_TARGET, "handler {:?} panicked while handling command {:?}: {}", instance.command_handler_name(), name, message);
+                            DispatchedCommand::failed(name, metadata, DispatchError::Panicked(message))
+                        })
+                        .await;
+
+                        if !dispatched.declined {
+                            break;
+                        }
+                        tracing::debug!(target: LOG_TARGET, "handler {:?} declined command {:?}", instance.command_handler_name(), name);
+                    }
+
+                    dispatched
+                })
+            }),
         }
     }
 
+    /// Sets the type name of the command the manager handles
+    pub(crate) fn for_message(mut self, message: &'static str) -> Self {
+        self.message = message;
+        self
+    }
+
+    /// The type name of the command, empty when the manager is not registered
+    pub(crate) fn message(&self) -> &'static str {
+        self.message
+    }
+
+    /// The names of the handlers, in the order they are called
+    pub(crate) fn handler_names(&self) -> Vec<&'static str> {
+        self.handlers
+            .load()
+            .iter()
+            .map(|handler| handler.command_handler_name())
+            .collect()
+    }
+
+    /// Adds a handler after the existing ones. It is called when
+    /// the handlers before it decline the command
+    pub async fn add(&self, handler: impl CommandHandler + 'static) -> &Self {
+        let handler: Arc<dyn CommandHandler> = Arc::new(handler);
+        self.handlers.rcu(|current| {
+            let mut handlers = Vec::clone(current);
+            handlers.push(Arc::clone(&handler));
+            handlers
+        });
+        self
+    }
+
     /// The name of the command
     pub fn name(&self) -> &String {
         &self.name
@@ -117,17 +211,24 @@ impl CommandHandlerManager {
 
     pub async fn next<M>(&self, middleware: M) -> &Self
     where
-        M: FnMut(DispatchedCommand, NextCommandMiddleware) -> BoxFuture<'static, DispatchedCommand>
+        M: Fn(DispatchedCommand, NextCommandMiddleware) -> BoxFuture<'static, DispatchedCommand>
             + Send
+            + Sync
             + 'static,
     {
-        self.middleware.next(middleware).await;
+        self.middleware.push(middleware);
         self
     }
 
     pub async fn handle(&self, dispatched: DispatchedCommand) -> DispatchedCommand {
-        let mut result = self.middleware.send(dispatched).await;
-        result.handled = true;
+        let name = dispatched.name();
+        let metadata = dispatched.metadata().clone();
+        let mut result = catch_panic(self.middleware.run(dispatched), |message| {
+            tracing::error!(target: LOG_TARGET, "a middleware of command {:?} panicked: {}", name, message);
+            DispatchedCommand::failed(name, metadata, DispatchError::Panicked(message))
+        })
+        .await;
+        result.handled = !result.declined;
 
         result
     }
@@ -169,6 +270,178 @@ mod test {
             .next(|c, n| Box::pin(async move { n.call(c).await }))
             .await;
 
-        assert_eq!(manager.handle_command(Cmd).await.handled(), true)
+        assert!(manager.handle_command(Cmd).await.handled())
+    }
+
+    #[tokio::test]
+    async fn test_handler_panic_is_caught() {
+        struct PanickingHandler;
+
+        #[async_trait::async_trait]
+        impl CommandHandler for PanickingHandler {
+            async fn handle_command(&self, _: DispatchedCommand) -> DispatchedCommand {
+                panic!("boom")
+            }
+        }
+
+        let manager = CommandHandlerManager::new(PanickingHandler).await;
+        manager
+            .next(|c, n| {
+                Box::pin(async move {
+                    let result = n.call(c).await;
+                    assert_eq!(
+                        result.error(),
+                        Some(&DispatchError::Panicked("boom".to_string()))
+                    );
+                    result
+                })
+            })
+            .await;
+
+        let result = manager.handle_command(Cmd).await;
+        assert!(!result.succeeded());
+        assert!(matches!(result.error(), Some(DispatchError::Panicked(_))));
+    }
+
+    #[tokio::test]
+    async fn test_handlers_can_decline() {
+        struct Pay {
+            method: &'static str,
+        }
+
+        struct CardHandler;
+
+        #[async_trait::async_trait]
+        impl CommandHandler for CardHandler {
+            async fn handle_command(&self, mut c: DispatchedCommand) -> DispatchedCommand {
+                if c.the_command::<Pay>().map(|p| p.method) != Some("card") {
+                    c.decline();
+                }
+                c
+            }
+        }
+
+        struct CashHandler;
+
+        #[async_trait::async_trait]
+        impl CommandHandler for CashHandler {
+            async fn handle_command(&self, mut c: DispatchedCommand) -> DispatchedCommand {
+                if c.the_command::<Pay>().map(|p| p.method) != Some("cash") {
+                    c.decline();
+                } else {
+                    c.metadata_mut().insert("paid_by", "cash");
+                }
+                c
+            }
+        }
+
+        let bus = Busstop::new();
+        bus.add_command_handler::<Pay>(CardHandler)
+            .await
+            .add_command_handler::<Pay>(CashHandler)
+            .await;
+
+        assert!(bus.dispatch_command(Pay { method: "card" }).await);
+
+        let paid = bus
+            .route_command(DispatchedCommand::new(
+                Box::new(Pay { method: "cash" }),
+                std::any::type_name::<Pay>(),
+            ))
+            .await;
+        assert!(paid.succeeded());
+        assert_eq!(paid.metadata().get("paid_by"), Some("cash"));
+
+        let declined = bus
+            .route_command(DispatchedCommand::new(
+                Box::new(Pay { method: "iou" }),
+                std::any::type_name::<Pay>(),
+            ))
+            .await;
+        assert!(!declined.handled());
+        assert!(declined.declined());
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn test_concurrent_adds_register_one_manager() {
+        struct Audit;
+
+        let bus = Arc::new(Busstop::new());
+        let adds = (0..16)
+            .map(|_| {
+                let bus = Arc::clone(&bus);
+                tokio::spawn(async move {
+                    bus.add_command_handler::<Audit>(CommandFnHandler::new(|_: Audit| async {}))
+                        .await;
+                })
+            })
+            .collect::<Vec<_>>();
+        for add in adds {
+            add.await.unwrap();
+        }
+
+        let introspection = bus.introspect().await;
+        let audit = introspection
+            .command(std::any::type_name::<Audit>())
+            .unwrap();
+        assert_eq!(audit.handlers.len(), 16);
+    }
+
+    #[tokio::test]
+    async fn test_command_reply() {
+        struct CreateUser {
+            name: &'static str,
+        }
+        impl DispatchableCommand for CreateUser {}
+        impl DispatchableCommandWithReply for CreateUser {
+            type Reply = u64;
+        }
+
+        struct RenameUser;
+        impl DispatchableCommand for RenameUser {}
+        impl DispatchableCommandWithReply for RenameUser {
+            type Reply = bool;
+        }
+
+        struct Renamed;
+
+        #[async_trait::async_trait]
+        impl CommandHandler for Renamed {
+            async fn handle_command(&self, dispatched: DispatchedCommand) -> DispatchedCommand {
+                dispatched.set_reply(true);
+                dispatched
+            }
+        }
+
+        let bus = Busstop::new();
+        assert_eq!(
+            bus.dispatch_command_with_reply(CreateUser { name: "ada" })
+                .await,
+            None
+        );
+
+        bus.register_command_fn::<CreateUser, _>(
+            |c: CreateUser| async move { c.name.len() as u64 },
+        )
+        .await
+        .register_command::<RenameUser>(Renamed)
+        .await;
+        assert_eq!(
+            bus.dispatch_command_with_reply(CreateUser { name: "ada" })
+                .await,
+            Some(3)
+        );
+        assert_eq!(
+            bus.dispatch_command_with_reply(RenameUser).await,
+            Some(true)
+        );
+
+        let mut dispatched = DispatchedCommand::from(RenameUser);
+        assert!(!dispatched.has_reply());
+        dispatched.set_reply(7_u64);
+        assert_eq!(dispatched.reply::<u64>(), Some(&7));
+        assert_eq!(dispatched.take_reply::<bool>(), None);
+        assert_eq!(dispatched.take_reply::<u64>(), Some(Box::new(7)));
+        assert!(!dispatched.has_reply());
     }
 }
@@ -0,0 +1,79 @@
+use std::{any::TypeId, future::Future, marker::PhantomData};
+
+use futures::future::BoxFuture;
+
+use super::{command_handler::CommandHandler, dispatched_command::DispatchedCommand};
+
+/// An async function or closure that can handle a command
+///
+/// This trait is implemented for every `Fn(C) -> impl Future<Output = R>`.
+/// A value other than `()` is set as the reply of the command
+///
+/// Since 0.3.0 the trait has the `Reply` associated type, types that
+/// implement it by hand must declare it
+pub trait CommandFn<C>: Send + Sync + 'static {
+    /// The type of the reply returned to the dispatcher
+    type Reply: Send + Sync + 'static;
+
+    /// Calls the function with the command
+    fn call(&self, command: C) -> BoxFuture<'static, Self::Reply>;
+}
+
+impl<C, F, Fut> CommandFn<C> for F
+where
+    F: Fn(C) -> Fut + Send + Sync + 'static,
+    Fut: Future + Send + 'static,
+    Fut::Output: Send + Sync + 'static,
+{
+    type Reply = Fut::Output;
+
+    fn call(&self, command: C) -> BoxFuture<'static, Self::Reply> {
+        Box::pin((self)(command))
+    }
+}
+
+/// Wraps a plain async function or closure so that it can be used
+/// as a command handler.
+///
+/// The command is taken out of the dispatched command and passed
+/// to the function by value. The value the function returns is set as
+/// the reply of the dispatched command, unless it is `()`.
+/// The function cannot decline, every command it receives is handled.
+pub struct CommandFnHandler<C, F> {
+    handler: F,
+    _command: PhantomData<fn(C)>,
+}
+
+impl<C, F: CommandFn<C>> CommandFnHandler<C, F> {
+    /// Create a new instance
+    pub fn new(handler: F) -> Self {
+        Self {
+            handler,
+            _command: PhantomData,
+        }
+    }
+}
+
+#[async_trait::async_trait]
+impl<C, F> CommandHandler for CommandFnHandler<C, F>
+where
+    C: Send + Sync + 'static,
+    F: CommandFn<C>,
+{
+    async fn handle_command(&self, mut dispatched: DispatchedCommand) -> DispatchedCommand {
+        if let Some(command) = dispatched.take_command::<C>() {
+            let reply = self.handler.call(*command).await;
+            if TypeId::of::<F::Reply>() != TypeId::of::<()>() {
+                dispatched.set_reply(reply);
+            }
+        } else {
+            tracing::error!(target: "dispatched command", "command {} has already been taken", dispatched.name());
+        }
+
+        dispatched
+    }
+
+    fn command_handler_name(&self) -> &'static str {
+        std::any::type_name::<F>()
+    }
+}
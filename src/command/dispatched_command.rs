@@ -1,23 +1,76 @@
-use std::any::Any;
+use std::{
+    any::{Any, TypeId},
+    sync::OnceLock,
+};
 
-use crate::DispatchableCommand;
+use crate::{DispatchError, DispatchableCommand, Metadata, family::Memberships};
 
 #[derive(Debug)]
 pub struct DispatchedCommand {
     inner: Option<Box<dyn Any + Send + Sync>>,
+    reply: OnceLock<(Box<dyn Any + Send + Sync>, &'static str)>,
     pub(crate) handled: bool,
-    name: String,
+    pub(crate) declined: bool,
+    families: Option<Memberships>,
+    name: &'static str,
+    metadata: Metadata,
+    error: Option<DispatchError>,
+    /// The rest of the middleware chain while the command goes through a tower layer
+    #[cfg(feature = "tower")]
+    pub(crate) next: Option<crate::NextCommandMiddleware>,
 }
 
 impl DispatchedCommand {
-    pub(crate) fn new(inner: Box<dyn Any + Send + Sync>, name: &str) -> Self {
+    pub(crate) fn new(inner: Box<dyn Any + Send + Sync>, name: &'static str) -> Self {
         Self {
             inner: Some(inner),
+            reply: OnceLock::new(),
             handled: false,
-            name: name.to_string(),
+            declined: false,
+            families: None,
+            name,
+            metadata: Metadata::new(),
+            error: None,
+            #[cfg(feature = "tower")]
+            next: None,
         }
     }
 
+    /// Creates a failed command that no longer holds the command. Used when the
+    /// original was consumed by something that failed
+    pub(crate) fn failed(name: &'static str, metadata: Metadata, error: DispatchError) -> Self {
+        let mut dispatched = Self::new(Box::new(()), name).with_metadata(metadata);
+        dispatched.inner = None;
+        dispatched.error = Some(error);
+        dispatched
+    }
+
+    /// The type id of the command, if it has not been taken
+    pub(crate) fn message_type_id(&self) -> Option<TypeId> {
+        self.inner.as_deref().map(Any::type_id)
+    }
+
+    /// The boxed command, if it has not been taken
+    pub(crate) fn boxed(&self) -> Option<&(dyn Any + Send + Sync)> {
+        self.inner.as_deref()
+    }
+
+    /// Takes the boxed command without knowing its type
+    pub(crate) fn take_boxed(&mut self) -> Option<Box<dyn Any + Send + Sync>> {
+        self.inner.take()
+    }
+
+    pub(crate) fn with_metadata(mut self, metadata: Metadata) -> Self {
+        self.metadata = metadata;
+        self
+    }
+
+    /// Sets the families the message was routed through
+    pub(crate) fn with_families(mut self, families: Memberships) -> Self {
+        self.families = Some(families);
+        self
+    }
+
     /// Returns a reference to (the real command)  the dispatched command
     pub fn the_command<T: 'static>(&self) -> Option<&T> {
         if let Some(inner) = &self.inner {
@@ -27,6 +80,13 @@ impl DispatchedCommand {
         }
     }
 
+    /// Returns the command as the trait object `T` of the family it was routed through.
+    /// Returns `None` when the command was not routed to a family handler
+    /// or is not a member of the family `T`
+    pub fn as_dyn<T: ?Sized + 'static>(&self) -> Option<&T> {
+        crate::family::cast(self.families.as_ref()?, self.inner.as_deref()?)
+    }
+
     /// Returns a mutable reference to (the real command)  the dispatched command
     pub fn the_command_mut<T: 'static>(&mut self) -> Option<&mut T> {
         if let Some(inner) = &mut self.inner {
@@ -45,14 +105,93 @@ impl DispatchedCommand {
         None
     }
 
+    /// Sets the reply that will be returned to the dispatcher
+    pub fn set_reply<R: Send + Sync + 'static>(&self, reply: R) {
+        let name = std::any::type_name::<R>();
+        if self.reply.set((Box::new(reply), name)).is_err() {
+            tracing::error!(target: "dispatched command", "reply can only be set once. Command: {}", &self.name);
+        }
+    }
+
+    /// Returns the reply set by the handler of the command
+    pub fn reply<R: 'static>(&self) -> Option<&R> {
+        self.reply.get().and_then(|(reply, _)| reply.downcast_ref())
+    }
+
+    /// Returns the command's reply
+    /// Subsequent call to this method or `reply` will return none
+    pub fn take_reply<R: 'static>(&mut self) -> Option<Box<R>> {
+        let (reply, name) = self.reply.take()?;
+        match reply.downcast() {
+            Ok(reply) => Some(reply),
+            Err(reply) => {
+                let _ = self.reply.set((reply, name));
+                None
+            }
+        }
+    }
+
+    /// Returns true if the handler set a reply
+    pub fn has_reply(&self) -> bool {
+        self.reply.get().is_some()
+    }
+
     /// Returns true if the command was handled
     pub fn handled(&self) -> bool {
         self.handled
     }
 
     /// The type name of the dispatched command
-    pub fn name(&self) -> &String {
-        &self.name
+    pub fn name(&self) -> &'static str {
+        self.name
+    }
+
+    /// Tells the bus that this handler does not handle this command.
+    /// The command is passed to the next handler registered for the type.
+    /// When every handler declines, the command is not handled
+    ///
+    /// A middleware that declines and returns without calling the next
+    /// middleware ends the chain, no handler is called and the command
+    /// is not handled
+    pub fn decline(&mut self) {
+        self.declined = true;
+    }
+
+    /// Returns true if the last handler declined the command
+    pub fn declined(&self) -> bool {
+        self.declined
+    }
+
+    /// Marks the command as failed
+    /// A handler or middleware that fails should return without
+    /// calling the next middleware
+    pub fn fail(&mut self, error: DispatchError) {
+        self.error = Some(error);
+    }
+
+    /// Returns the reason the command failed
+    pub fn error(&self) -> Option<&DispatchError> {
+        self.error.as_ref()
+    }
+
+    /// Returns true if the command was marked as failed
+    pub fn is_failed(&self) -> bool {
+        self.error.is_some()
+    }
+
+    /// Returns true if the command was handled and did not fail
+    pub fn succeeded(&self) -> bool {
+        self.handled && self.error.is_none()
+    }
+
+    /// The metadata passed along with the command
+    pub fn metadata(&self) -> &Metadata {
+        &self.metadata
+    }
+
+    /// Returns a mutable reference to the metadata
+    pub fn metadata_mut(&mut self) -> &mut Metadata {
+        &mut self.metadata
     }
 
     /// Compares the dispatched type with "C"
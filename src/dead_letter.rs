@@ -0,0 +1,448 @@
+#[cfg(feature = "json")]
+mod file;
+mod memory;
+
+use std::{any::Any, fmt::Display, sync::Arc};
+
+#[cfg(feature = "json")]
+pub use file::FileDeadLetters;
+pub use memory::MemoryDeadLetters;
+
+use crate::{Busstop, DispatchError, DispatchedCommand, Metadata};
+#[cfg(feature = "serde")]
+use crate::{Codec, CodecError, Codecs, DynamicDispatchError, dynamic::EncodeMessageFn};
+
+const LOG_TARGET: &str = "bus_stop";
+
+/// The reason a command was sent to the dead-letter sink
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub enum DeadLetterReason {
+    /// The command does not have a handler
+    Unhandled,
+    /// The handler failed or panicked
+    Failed(DispatchError),
+}
+
+impl Display for DeadLetterReason {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::Unhandled => write!(f, "unhandled"),
+            Self::Failed(error) => error.fmt(f),
+        }
+    }
+}
+
+/// What is known about a dead letter
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct DeadLetterEntry {
+    /// The id given by the sink
+    pub id: u64,
+    /// The type name of the command
+    pub name: String,
+    /// Why the command is a dead letter
+    pub reason: DeadLetterReason,
+    /// The metadata that was dispatched along with the command
+    pub metadata: Metadata,
+    /// True if the command can be dispatched again. A handler that
+    /// takes the command, such as a function registered with
+    /// `register_command_fn`, leaves nothing to dispatch again when it
+    /// fails. Commands whose handler panicked are never replayable,
+    /// the command is lost with the handler. Unhandled commands are
+    /// always replayable
+    pub replayable: bool,
+}
+
+/// A command serialized by a sink that stores dead letters outside of the process
+#[cfg(feature = "serde")]
+#[derive(Debug, Clone, PartialEq, Eq, serde::Serialize, serde::Deserialize)]
+pub struct EncodedCommand {
+    /// The message name the command was registered with
+    pub name: String,
+    /// The name of the codec used to encode the command
+    pub codec: String,
+    /// The encoded command
+    pub bytes: Vec<u8>,
+}
+
+/// A command that was not handled or whose handler failed
+pub struct DeadLetter {
+    entry: DeadLetterEntry,
+    command: Option<(Box<dyn Any + Send + Sync>, &'static str)>,
+    #[cfg(feature = "serde")]
+    encoder: Option<(&'static str, EncodeMessageFn)>,
+    #[cfg(feature = "serde")]
+    encoded: Option<EncodedCommand>,
+}
+
+impl DeadLetter {
+    /// What is known about the dead letter
+    pub fn entry(&self) -> &DeadLetterEntry {
+        &self.entry
+    }
+
+    /// Sets the id of the letter. Called by the sink when the letter is stored
+    pub fn set_id(&mut self, id: u64) {
+        self.entry.id = id;
+    }
+
+    /// Serializes the command. Returns `None` when the command is no longer held
+    /// or was not registered with `register_dynamic_command`
+    #[cfg(feature = "serde")]
+    pub fn encode(&self, codec: &dyn Codec) -> Option<Result<EncodedCommand, CodecError>> {
+        if let Some(encoded) = &self.encoded {
+            return Some(Ok(encoded.clone()));
+        }
+
+        let (command, _) = self.command.as_ref()?;
+        let (name, encode) = self.encoder?;
+        Some(encode(codec, command.as_ref()).map(|bytes| EncodedCommand {
+            name: name.to_string(),
+            codec: codec.name().to_string(),
+            bytes,
+        }))
+    }
+
+    /// Rebuilds a letter loaded by a sink that stores dead letters
+    /// outside of the process
+    #[cfg(feature = "serde")]
+    pub fn from_encoded(mut entry: DeadLetterEntry, encoded: Option<EncodedCommand>) -> Self {
+        entry.replayable = encoded.is_some();
+        Self {
+            entry,
+            command: None,
+            encoder: None,
+            encoded,
+        }
+    }
+}
+
+/// Stores the commands that were not handled and the commands whose handler failed
+///
+/// The sink gives every letter an id. Use `Busstop::dead_letters` to list
+/// them and `Busstop::redispatch_dead_letter` to dispatch one again
+#[async_trait::async_trait]
+pub trait DeadLetterSink: Send + Sync + 'static {
+    /// Stores the letter and returns the id it was given
+    async fn store(&self, letter: DeadLetter) -> u64;
+
+    /// The stored letters, oldest first
+    async fn list(&self) -> Vec<DeadLetterEntry>;
+
+    /// Returns the letter with this id
+    async fn get(&self, id: u64) -> Option<DeadLetterEntry>;
+
+    /// Removes the letter with this id and returns it
+    async fn take(&self, id: u64) -> Option<DeadLetter>;
+
+    /// The codecs the sink encodes commands with, the preferred one is
+    /// used to encode. A stored command is decoded with the codec it names
+    #[cfg(feature = "serde")]
+    fn codecs(&self) -> Codecs {
+        Codecs::default()
+    }
+}
+
+/// Error returned when a dead letter could not be dispatched again
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum RedispatchError {
+    /// No dead-letter sink is set
+    NoSink,
+    /// The sink does not have a letter with this id
+    NotFound(u64),
+    /// The letter does not hold the command anymore
+    NotReplayable(u64),
+    /// The stored command could not be decoded
+    #[cfg(feature = "serde")]
+    Dynamic(DynamicDispatchError),
+}
+
+impl Display for RedispatchError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::NoSink => write!(f, "no dead-letter sink is set"),
+            Self::NotFound(id) => write!(f, "dead letter {} not found", id),
+            Self::NotReplayable(id) => write!(f, "dead letter {} cannot be dispatched again", id),
+            #[cfg(feature = "serde")]
+            Self::Dynamic(e) => e.fmt(f),
+        }
+    }
+}
+
+impl std::error::Error for RedispatchError {}
+
+impl Busstop {
+    /// Sets the sink that receives the commands that were not handled
+    /// and the commands whose handler failed or panicked.
+    /// The command is moved out of the dispatched command into the sink
+    ///
+    /// Nothing is encoded while the commands are dispatched, a sink that
+    /// stores letters outside of the process encodes the command of the
+    /// letter it receives
+    pub async fn set_dead_letter_sink(&self, sink: impl DeadLetterSink) -> &Self {
+        self.dead_letters.store(Some(Arc::new(Box::new(sink))));
+        self
+    }
+
+    /// The letters stored by the dead-letter sink
+    pub async fn dead_letters(&self) -> Vec<DeadLetterEntry> {
+        match self.dead_letters.load_full() {
+            Some(sink) => sink.list().await,
+            None => Vec::new(),
+        }
+    }
+
+    /// Returns the dead letter with this id
+    pub async fn dead_letter(&self, id: u64) -> Option<DeadLetterEntry> {
+        self.dead_letters.load_full()?.get(id).await
+    }
+
+    /// Removes the letter from the sink and dispatches its command again.
+    /// Returns true if the command was handled without failing.
+    /// A command that fails again is stored under a new id
+    pub async fn redispatch_dead_letter(&self, id: u64) -> Result<bool, RedispatchError> {
+        let sink = self
+            .dead_letters
+            .load_full()
+            .ok_or(RedispatchError::NoSink)?;
+        match sink.get(id).await {
+            Some(entry) if entry.replayable => (),
+            Some(_) => return Err(RedispatchError::NotReplayable(id)),
+            None => return Err(RedispatchError::NotFound(id)),
+        }
+
+        let letter = sink.take(id).await.ok_or(RedispatchError::NotFound(id))?;
+        let metadata = letter.entry.metadata.clone();
+        let dispatched = match letter.command {
+            Some((command, name)) => DispatchedCommand::new(command, name),
+            #[cfg(feature = "serde")]
+            None => match self
+                .decode_dead_letter(id, &letter.encoded, &sink.codecs())
+                .await
+            {
+                Ok(dispatched) => dispatched,
+                Err(e) => {
+                    sink.store(DeadLetter::from_encoded(letter.entry, letter.encoded))
+                        .await;
+                    return Err(e);
+                }
+            },
+            #[cfg(not(feature = "serde"))]
+            None => return Err(RedispatchError::NotReplayable(id)),
+        };
+
+        tracing::debug!(target: LOG_TARGET, "dispatching dead letter {} again: {:?}", id, dispatched.name());
+        Ok(self
+            .route_command(dispatched.with_metadata(metadata))
+            .await
+            .succeeded())
+    }
+
+    #[cfg(feature = "serde")]
+    async fn decode_dead_letter(
+        &self,
+        id: u64,
+        encoded: &Option<EncodedCommand>,
+        codecs: &Codecs,
+    ) -> Result<DispatchedCommand, RedispatchError> {
+        let Some(encoded) = encoded else {
+            return Err(RedispatchError::NotReplayable(id));
+        };
+        let Some(codec) = codecs.get(&encoded.codec) else {
+            return Err(RedispatchError::Dynamic(DynamicDispatchError::Codec(
+                CodecError::new(format!("codec {} is not available", encoded.codec)),
+            )));
+        };
+
+        self.decode_command(&encoded.name, &encoded.bytes, codec.as_ref())
+            .await
+            .map_err(RedispatchError::Dynamic)
+    }
+
+    /// Moves the command to the dead-letter sink when it was not handled
+    /// or its handler failed
+    pub(crate) async fn dead_letter_command(
+        &self,
+        mut dispatched: DispatchedCommand,
+    ) -> DispatchedCommand {
+        let reason = match dispatched.error() {
+            None if !dispatched.handled() => DeadLetterReason::Unhandled,
+            Some(error @ (DispatchError::Failed(_) | DispatchError::Panicked(_)))
+                if dispatched.handled() =>
+            {
+                DeadLetterReason::Failed(error.clone())
+            }
+            _ => return dispatched,
+        };
+        let Some(sink) = self.dead_letters.load_full() else {
+            return dispatched;
+        };
+
+        let name = dispatched.name();
+        let command = dispatched.take_boxed().map(|command| (command, name));
+        // The command is only encoded by a sink that stores it outside of the process
+        #[cfg(feature = "serde")]
+        let encoder = command
+            .as_ref()
+            .and_then(|(command, _)| self.command_encoder(command.as_ref().type_id()));
+        let replayable = command.is_some();
+        let letter = DeadLetter {
+            entry: DeadLetterEntry {
+                id: 0,
+                name: name.to_string(),
+                reason,
+                metadata: dispatched.metadata().clone(),
+                replayable,
+            },
+            command,
+            #[cfg(feature = "serde")]
+            encoder,
+            #[cfg(feature = "serde")]
+            encoded: None,
+        };
+
+        let id = sink.store(letter).await;
+        tracing::warn!(target: LOG_TARGET, "command {:?} was stored as dead letter {}", name, id);
+
+        dispatched
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::sync::atomic::{AtomicBool, Ordering};
+
+    use super::*;
+
+    struct SendInvoice {
+        number: u32,
+    }
+
+    struct ChargeCard;
+
+    #[tokio::test]
+    async fn test_unhandled_command_is_dead_lettered_and_redispatched() {
+        let bus = Busstop::new();
+        assert!(!bus.dispatch_command(SendInvoice { number: 7 }).await);
+        assert!(bus.dead_letters().await.is_empty());
+
+        bus.set_dead_letter_sink(MemoryDeadLetters::new()).await;
+        let metadata = Metadata::from_iter([("tenant", "acme")]);
+        assert!(
+            !bus.dispatch_command_with_metadata(SendInvoice { number: 7 }, metadata.clone())
+                .await
+        );
+
+        let letters = bus.dead_letters().await;
+        assert_eq!(letters.len(), 1);
+        assert_eq!(letters[0].reason, DeadLetterReason::Unhandled);
+        assert_eq!(letters[0].metadata, metadata);
+        assert!(letters[0].replayable);
+        assert_eq!(
+            bus.dead_letter(letters[0].id).await.as_ref(),
+            Some(&letters[0])
+        );
+
+        bus.register_command_fn::<SendInvoice, _>(|c: SendInvoice| async move {
+            assert_eq!(c.number, 7);
+        })
+        .await;
+        assert_eq!(bus.redispatch_dead_letter(letters[0].id).await, Ok(true));
+        assert!(bus.dead_letters().await.is_empty());
+        assert_eq!(
+            bus.redispatch_dead_letter(letters[0].id).await,
+            Err(RedispatchError::NotFound(letters[0].id))
+        );
+    }
+
+    #[tokio::test]
+    async fn test_failed_command_is_dead_lettered() {
+        let bus = Busstop::new();
+        bus.set_dead_letter_sink(MemoryDeadLetters::new())
+            .await
+            .register_command_fn::<ChargeCard, _>(|_: ChargeCard| async {
+                panic!("card reader is offline");
+            })
+            .await;
+
+        assert!(bus.dispatch_command(ChargeCard).await);
+
+        let letters = bus.dead_letters().await;
+        assert_eq!(
+            letters[0].reason,
+            DeadLetterReason::Failed(DispatchError::Panicked(
+                "card reader is offline".to_string()
+            ))
+        );
+        assert!(!letters[0].replayable);
+        assert_eq!(
+            bus.redispatch_dead_letter(letters[0].id).await,
+            Err(RedispatchError::NotReplayable(letters[0].id))
+        );
+    }
+
+    #[tokio::test]
+    async fn test_failed_command_that_was_not_taken_is_replayable() {
+        static OFFLINE: AtomicBool = AtomicBool::new(true);
+
+        struct Mailer;
+
+        #[async_trait::async_trait]
+        impl crate::CommandHandler for Mailer {
+            async fn handle_command(&self, mut c: DispatchedCommand) -> DispatchedCommand {
+                if OFFLINE.load(Ordering::SeqCst) {
+                    c.fail(DispatchError::Failed("mailer is offline".to_string()));
+                } else {
+                    assert_eq!(c.the_command::<SendInvoice>().map(|c| c.number), Some(9));
+                }
+                c
+            }
+        }
+
+        let bus = Busstop::new();
+        bus.set_dead_letter_sink(MemoryDeadLetters::new())
+            .await
+            .register_command::<SendInvoice>(Mailer)
+            .await;
+
+        assert!(bus.dispatch_command(SendInvoice { number: 9 }).await);
+        let letters = bus.dead_letters().await;
+        assert!(letters[0].replayable);
+
+        OFFLINE.store(false, Ordering::SeqCst);
+        assert_eq!(bus.redispatch_dead_letter(letters[0].id).await, Ok(true));
+        assert!(bus.dead_letters().await.is_empty());
+    }
+
+    #[tokio::test]
+    async fn test_panicked_command_that_was_not_taken_is_not_replayable() {
+        struct Printer;
+
+        #[async_trait::async_trait]
+        impl crate::CommandHandler for Printer {
+            async fn handle_command(&self, c: DispatchedCommand) -> DispatchedCommand {
+                c.set_reply("queued");
+                panic!("printer is jammed");
+            }
+        }
+
+        let bus = Busstop::new();
+        bus.set_dead_letter_sink(MemoryDeadLetters::new())
+            .await
+            .register_command::<SendInvoice>(Printer)
+            .await;
+
+        assert!(bus.dispatch_command(SendInvoice { number: 4 }).await);
+        let letters = bus.dead_letters().await;
+        assert_eq!(
+            letters[0].reason,
+            DeadLetterReason::Failed(DispatchError::Panicked("printer is jammed".to_string()))
+        );
+        assert!(!letters[0].replayable);
+        assert_eq!(
+            bus.redispatch_dead_letter(letters[0].id).await,
+            Err(RedispatchError::NotReplayable(letters[0].id))
+        );
+    }
+}
@@ -0,0 +1,356 @@
+use std::{
+    collections::BTreeMap,
+    fs::{File, OpenOptions},
+    io::{self, BufRead, BufReader, Write},
+    path::PathBuf,
+};
+
+use tokio::sync::Mutex;
+
+use super::{DeadLetter, DeadLetterEntry, DeadLetterSink, EncodedCommand};
+use crate::Codecs;
+
+const LOG_TARGET: &str = "bus_stop";
+
+#[derive(serde::Serialize, serde::Deserialize)]
+struct StoredLetter {
+    entry: DeadLetterEntry,
+    command: Option<EncodedCommand>,
+}
+
+/// A line of the file. The last id given is written at the top when the
+/// file is rewritten, so that the id of a letter that was taken is not
+/// given again
+#[derive(serde::Serialize, serde::Deserialize)]
+#[serde(untagged)]
+enum Line {
+    Letter(StoredLetter),
+    LastId { last_id: u64 },
+}
+
+#[derive(Default)]
+struct Letters {
+    letters: BTreeMap<u64, StoredLetter>,
+    last_id: u64,
+}
+
+/// Keeps the dead letters in a file, one JSON object per line
+///
+/// Only commands registered with `register_dynamic_command` can be
+/// dispatched again, the others are stored without the command.
+/// The file is written on the blocking thread pool, every letter that
+/// is taken rewrites the whole file
+pub struct FileDeadLetters {
+    path: PathBuf,
+    codecs: Codecs,
+    letters: Mutex<Letters>,
+}
+
+impl FileDeadLetters {
+    /// Opens the file and loads the letters stored by a previous run.
+    /// The file is created when the first letter is stored
+    pub fn open(path: impl Into<PathBuf>) -> io::Result<Self> {
+        let path = path.into();
+        let mut letters = Letters::default();
+
+        match File::open(&path) {
+            Ok(file) => {
+                for line in BufReader::new(file).lines() {
+                    let line = line?;
+                    if line.trim().is_empty() {
+                        continue;
+                    }
+                    match serde_json::from_str(&line).map_err(io::Error::other)? {
+                        Line::Letter(letter) => {
+                            letters.last_id = letters.last_id.max(letter.entry.id);
+                            letters.letters.insert(letter.entry.id, letter);
+                        }
+                        Line::LastId { last_id } => {
+                            letters.last_id = letters.last_id.max(last_id);
+                        }
+                    }
+                }
+            }
+            Err(e) if e.kind() == io::ErrorKind::NotFound => (),
+            Err(e) => return Err(e),
+        }
+
+        Ok(Self {
+            path,
+            codecs: Codecs::default(),
+            letters: Mutex::new(letters),
+        })
+    }
+
+    /// Sets the codecs the commands are encoded with, the preferred one
+    /// encodes. Defaults to the codecs enabled via cargo features
+    pub fn with_codecs(mut self, codecs: Codecs) -> Self {
+        self.codecs = codecs;
+        self
+    }
+
+    async fn append(&self, letter: &StoredLetter) -> io::Result<()> {
+        let line = serde_json::to_string(letter).map_err(io::Error::other)?;
+        let path = self.path.clone();
+
+        blocking(move || {
+            let mut file = OpenOptions::new().create(true).append(true).open(path)?;
+            writeln!(file, "{}", line)
+        })
+        .await
+    }
+
+    async fn rewrite(&self, letters: &Letters) -> io::Result<()> {
+        let mut content = serde_json::to_string(&Line::LastId {
+            last_id: letters.last_id,
+        })
+        .map_err(io::Error::other)?;
+        content.push('\n');
+        for letter in letters.letters.values() {
+            content.push_str(&serde_json::to_string(letter).map_err(io::Error::other)?);
+            content.push('\n');
+        }
+        let path = self.path.clone();
+
+        blocking(move || {
+            let temporary = path.with_extension("tmp");
+            let mut file = File::create(&temporary)?;
+            file.write_all(content.as_bytes())?;
+            file.sync_all()?;
+
+            std::fs::rename(temporary, path)
+        })
+        .await
+    }
+}
+
+async fn blocking(write: impl FnOnce() -> io::Result<()> + Send + 'static) -> io::Result<()> {
+    tokio::task::spawn_blocking(write)
+        .await
+        .unwrap_or_else(|e| Err(io::Error::other(e)))
+}
+
+#[async_trait::async_trait]
+impl DeadLetterSink for FileDeadLetters {
+    async fn store(&self, letter: DeadLetter) -> u64 {
+        let encoded = self
+            .codecs
+            .preferred()
+            .and_then(|codec| letter.encode(codec.as_ref()));
+        let command = match encoded {
+            Some(Ok(command)) => Some(command),
+            Some(Err(e)) => {
+                tracing::error!(target: LOG_TARGET, "dead letter {:?} could not be encoded: {}", letter.entry().name, e);
+                None
+            }
+            None => None,
+        };
+
+        // The lock is held while writing, so that lines are written in
+        // the order the ids were given
+        let mut letters = self.letters.lock().await;
+        letters.last_id += 1;
+        let id = letters.last_id;
+        let mut entry = letter.entry().clone();
+        entry.id = id;
+        entry.replayable = command.is_some();
+
+        let stored = StoredLetter { entry, command };
+        if let Err(e) = self.append(&stored).await {
+            tracing::error!(target: LOG_TARGET, "dead letter {} could not be written to {:?}: {}", id, self.path, e);
+        }
+        letters.letters.insert(id, stored);
+
+        id
+    }
+
+    async fn list(&self) -> Vec<DeadLetterEntry> {
+        self.letters
+            .lock()
+            .await
+            .letters
+            .values()
+            .map(|letter| letter.entry.clone())
+            .collect()
+    }
+
+    async fn get(&self, id: u64) -> Option<DeadLetterEntry> {
+        self.letters
+            .lock()
+            .await
+            .letters
+            .get(&id)
+            .map(|letter| letter.entry.clone())
+    }
+
+    async fn take(&self, id: u64) -> Option<DeadLetter> {
+        let mut letters = self.letters.lock().await;
+        let letter = letters.letters.remove(&id)?;
+        if let Err(e) = self.rewrite(&letters).await {
+            tracing::error!(target: LOG_TARGET, "dead letters could not be written to {:?}: {}", self.path, e);
+        }
+
+        Some(DeadLetter::from_encoded(letter.entry, letter.command))
+    }
+
+    fn codecs(&self) -> Codecs {
+        self.codecs.clone()
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use serde::{Deserialize, Serialize};
+
+    use super::*;
+    use crate::{Busstop, Codec, CodecError, DeadLetterReason, JsonCodec, SerializableMessage};
+
+    #[derive(Serialize, Deserialize)]
+    struct ShipOrder {
+        order: u32,
+    }
+    impl SerializableMessage for ShipOrder {}
+
+    #[tokio::test]
+    async fn test_dead_letters_survive_a_restart() {
+        let path =
+            std::env::temp_dir().join(format!("busstop-dead-letters-{}.jsonl", std::process::id()));
+        let _ = std::fs::remove_file(&path);
+
+        let bus = Busstop::new();
+        bus.register_dynamic_command::<ShipOrder>()
+            .await
+            .set_dead_letter_sink(FileDeadLetters::open(&path).unwrap())
+            .await;
+        assert!(!bus.dispatch_command(ShipOrder { order: 12 }).await);
+        drop(bus);
+
+        let bus = Busstop::new();
+        bus.register_dynamic_command::<ShipOrder>()
+            .await
+            .set_dead_letter_sink(FileDeadLetters::open(&path).unwrap())
+            .await
+            .register_command_fn::<ShipOrder, _>(|c: ShipOrder| async move {
+                assert_eq!(c.order, 12);
+            })
+            .await;
+
+        let letters = bus.dead_letters().await;
+        assert_eq!(letters.len(), 1);
+        assert_eq!(letters[0].reason, DeadLetterReason::Unhandled);
+        assert!(letters[0].replayable);
+
+        assert_eq!(bus.redispatch_dead_letter(letters[0].id).await, Ok(true));
+        assert!(
+            FileDeadLetters::open(&path)
+                .unwrap()
+                .list()
+                .await
+                .is_empty()
+        );
+
+        std::fs::remove_file(&path).unwrap();
+    }
+
+    /// JSON under another name
+    struct JsonV2;
+
+    impl Codec for JsonV2 {
+        fn name(&self) -> &'static str {
+            "json-v2"
+        }
+
+        fn encode(&self, value: &dyn erased_serde::Serialize) -> Result<Vec<u8>, CodecError> {
+            JsonCodec.encode(value)
+        }
+
+        fn decode(
+            &self,
+            bytes: &[u8],
+            visitor: crate::DecodeVisitor<'_>,
+        ) -> Result<(), CodecError> {
+            JsonCodec.decode(bytes, visitor)
+        }
+    }
+
+    #[tokio::test]
+    async fn test_dead_letters_use_the_codecs_of_the_sink() {
+        let path = std::env::temp_dir().join(format!(
+            "busstop-dead-letters-codecs-{}.jsonl",
+            std::process::id()
+        ));
+        let _ = std::fs::remove_file(&path);
+
+        let bus = Busstop::new();
+        bus.register_dynamic_command::<ShipOrder>()
+            .await
+            .set_dead_letter_sink(
+                FileDeadLetters::open(&path)
+                    .unwrap()
+                    .with_codecs(Codecs::new().with(JsonV2)),
+            )
+            .await;
+        assert!(!bus.dispatch_command(ShipOrder { order: 3 }).await);
+
+        bus.register_command_fn::<ShipOrder, _>(|c: ShipOrder| async move {
+            assert_eq!(c.order, 3);
+        })
+        .await;
+        let letters = bus.dead_letters().await;
+        assert_eq!(bus.redispatch_dead_letter(letters[0].id).await, Ok(true));
+
+        std::fs::remove_file(&path).unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_taken_ids_are_not_given_again() {
+        let path = std::env::temp_dir().join(format!(
+            "busstop-dead-letters-ids-{}.jsonl",
+            std::process::id()
+        ));
+        let _ = std::fs::remove_file(&path);
+
+        let bus = Busstop::new();
+        bus.register_dynamic_command::<ShipOrder>()
+            .await
+            .set_dead_letter_sink(FileDeadLetters::open(&path).unwrap())
+            .await;
+        bus.dispatch_command(ShipOrder { order: 1 }).await;
+        bus.dispatch_command(ShipOrder { order: 2 }).await;
+        assert_eq!(
+            bus.redispatch_dead_letter(2).await,
+            Ok(false),
+            "the command is still not handled"
+        );
+        assert_eq!(
+            bus.dead_letters()
+                .await
+                .iter()
+                .map(|l| l.id)
+                .collect::<Vec<_>>(),
+            vec![1, 3]
+        );
+        drop(bus);
+
+        let sink = FileDeadLetters::open(&path).unwrap();
+        assert!(sink.take(3).await.is_some());
+        drop(sink);
+
+        let bus = Busstop::new();
+        bus.register_dynamic_command::<ShipOrder>()
+            .await
+            .set_dead_letter_sink(FileDeadLetters::open(&path).unwrap())
+            .await;
+        bus.dispatch_command(ShipOrder { order: 3 }).await;
+        assert_eq!(
+            bus.dead_letters()
+                .await
+                .iter()
+                .map(|l| l.id)
+                .collect::<Vec<_>>(),
+            vec![1, 4]
+        );
+
+        std::fs::remove_file(&path).unwrap();
+    }
+}
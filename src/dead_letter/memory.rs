@@ -0,0 +1,65 @@
+use std::{
+    collections::BTreeMap,
+    sync::{
+        Mutex, PoisonError,
+        atomic::{AtomicU64, Ordering},
+    },
+};
+
+use super::{DeadLetter, DeadLetterEntry, DeadLetterSink};
+
+/// Keeps the dead letters in memory
+///
+/// The commands are kept as they were dispatched. A command whose
+/// handler took it and then failed cannot be dispatched again.
+/// The letters are lost when the process exits
+#[derive(Default)]
+pub struct MemoryDeadLetters {
+    letters: Mutex<BTreeMap<u64, DeadLetter>>,
+    last_id: AtomicU64,
+}
+
+impl MemoryDeadLetters {
+    /// Create a new instance
+    pub fn new() -> Self {
+        Self::default()
+    }
+}
+
+#[async_trait::async_trait]
+impl DeadLetterSink for MemoryDeadLetters {
+    async fn store(&self, mut letter: DeadLetter) -> u64 {
+        let id = self.last_id.fetch_add(1, Ordering::SeqCst) + 1;
+        letter.set_id(id);
+        self.letters
+            .lock()
+            .unwrap_or_else(PoisonError::into_inner)
+            .insert(id, letter);
+
+        id
+    }
+
+    async fn list(&self) -> Vec<DeadLetterEntry> {
+        self.letters
+            .lock()
+            .unwrap_or_else(PoisonError::into_inner)
+            .values()
+            .map(|letter| letter.entry().clone())
+            .collect()
+    }
+
+    async fn get(&self, id: u64) -> Option<DeadLetterEntry> {
+        self.letters
+            .lock()
+            .unwrap_or_else(PoisonError::into_inner)
+            .get(&id)
+            .map(|letter| letter.entry().clone())
+    }
+
+    async fn take(&self, id: u64) -> Option<DeadLetter> {
+        self.letters
+            .lock()
+            .unwrap_or_else(PoisonError::into_inner)
+            .remove(&id)
+    }
+}
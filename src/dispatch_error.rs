@@ -0,0 +1,44 @@
+use std::fmt::Display;
+
+use crate::ValidationErrors;
+
+/// The reason a dispatched command or query failed
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+#[non_exhaustive]
+pub enum DispatchError {
+    /// The message was rejected because it is not valid. Use it when the
+    /// reason is not tied to a field, use `Validation` for errors per field
+    Invalid(String),
+    /// The message did not pass `Validate`. Holds the errors of each field,
+    /// which the HTTP and JSON-RPC adapters return to the caller
+    Validation(ValidationErrors),
+    /// The caller is not allowed to dispatch the message
+    Forbidden(String),
+    /// The message was rejected because too many were dispatched.
+    /// Rate-limiting middlewares and layers fail with it, the HTTP
+    /// gateway answers 429
+    RateLimited,
+    /// The handler or a middleware failed
+    Failed(String),
+    /// The handler or a middleware panicked. Holds the panic message
+    ///
+    /// The command or query and any reply are lost with the handler
+    /// that panicked, so a panicked command cannot be dispatched again
+    Panicked(String),
+}
+
+impl Display for DispatchError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::Invalid(reason) => write!(f, "invalid: {}", reason),
+            Self::Validation(errors) => write!(f, "validation failed: {}", errors),
+            Self::Forbidden(reason) => write!(f, "forbidden: {}", reason),
+            Self::RateLimited => write!(f, "rate limited"),
+            Self::Failed(reason) => write!(f, "failed: {}", reason),
+            Self::Panicked(message) => write!(f, "panicked: {}", message),
+        }
+    }
+}
+
+impl std::error::Error for DispatchError {}
@@ -0,0 +1,467 @@
+mod codec;
+mod envelope;
+
+use std::{
+    any::{Any, TypeId},
+    collections::HashMap,
+    fmt::Display,
+};
+
+pub use codec::*;
+pub use envelope::*;
+use serde::{Serialize, de::DeserializeOwned};
+
+use crate::{Busstop, DispatchError, DispatchedCommand, DispatchedQuery};
+
+const LOG_TARGET: &str = "bus_stop";
+
+/// A command or query that can be dispatched by name from bytes
+pub trait SerializableMessage: Serialize + DeserializeOwned + Send + Sync + 'static {
+    /// The name this message is known by outside of this process
+    /// By default, the path to the type is used
+    fn message_name() -> &'static str {
+        std::any::type_name::<Self>()
+    }
+}
+
+type DecodeFn = fn(&dyn Codec, &[u8]) -> Result<Box<dyn Any + Send + Sync>, CodecError>;
+pub(crate) type EncodeMessageFn =
+    fn(&dyn Codec, &(dyn Any + Send + Sync)) -> Result<Vec<u8>, CodecError>;
+pub(crate) type EncodeValueFn =
+    fn(&dyn Codec, &DispatchedQuery) -> Result<Option<Vec<u8>>, CodecError>;
+type EnvelopeResult = (bool, Option<DispatchError>, Vec<u8>);
+
+/// The message name and the encoder of the commands registered with
+/// `register_dynamic_command`, read without locking by the dead-letter path
+pub(crate) type CommandEncoders = HashMap<TypeId, (&'static str, EncodeMessageFn)>;
+
+#[derive(Clone, Copy)]
+struct DynamicCommand {
+    type_name: &'static str,
+    decode: DecodeFn,
+}
+
+#[derive(Clone, Copy)]
+struct DynamicQuery {
+    type_name: &'static str,
+    decode: DecodeFn,
+    encode_value: EncodeValueFn,
+}
+
+/// Maps message names to the functions that rebuild the messages
+#[derive(Default)]
+pub(crate) struct DynamicRegistry {
+    commands: HashMap<&'static str, DynamicCommand>,
+    queries: HashMap<&'static str, DynamicQuery>,
+}
+
+fn decode<M: SerializableMessage>(
+    codec: &dyn Codec,
+    bytes: &[u8],
+) -> Result<Box<dyn Any + Send + Sync>, CodecError> {
+    Ok(Box::new(codec.decode_value::<M>(bytes)?))
+}
+
+fn encode_message<M: SerializableMessage>(
+    codec: &dyn Codec,
+    message: &(dyn Any + Send + Sync),
+) -> Result<Vec<u8>, CodecError> {
+    match message.downcast_ref::<M>() {
+        Some(message) => codec.encode_value(message),
+        None => Err(CodecError::new(format!(
+            "expected {}",
+            std::any::type_name::<M>()
+        ))),
+    }
+}
+
+fn encode_value<V: Serialize + 'static>(
+    codec: &dyn Codec,
+    dispatched: &DispatchedQuery,
+) -> Result<Option<Vec<u8>>, CodecError> {
+    dispatched
+        .value::<V>()
+        .map(|value| codec.encode_value(value))
+        .transpose()
+}
+
+/// Error returned when a message could not be dispatched by name
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum DynamicDispatchError {
+    /// No message is registered with this name
+    UnknownMessage(String),
+    /// The payload or the value could not be (de)serialized
+    Codec(CodecError),
+}
+
+impl Display for DynamicDispatchError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::UnknownMessage(name) => write!(f, "unknown message: {}", name),
+            Self::Codec(e) => e.fmt(f),
+        }
+    }
+}
+
+impl std::error::Error for DynamicDispatchError {}
+
+impl From<CodecError> for DynamicDispatchError {
+    fn from(value: CodecError) -> Self {
+        Self::Codec(value)
+    }
+}
+
+impl Busstop {
+    /// Allows the command to be dispatched by its message name
+    pub async fn register_dynamic_command<C: SerializableMessage>(&self) -> &Self {
+        let name = C::message_name();
+        let mut lock = self.dynamic.write().await;
+
+        if let Some(existing) = lock.commands.get(name)
+            && existing.type_name != std::any::type_name::<C>()
+        {
+            tracing::error!(target: LOG_TARGET, "message name {} is already used by {}", name, existing.type_name);
+            panic!(
+                "message name {} is already used by {}",
+                name, existing.type_name
+            );
+        }
+
+        tracing::debug!(target: LOG_TARGET, "registered dynamic command {:?} for {:?}", name, std::any::type_name::<C>());
+        lock.commands.insert(
+            name,
+            DynamicCommand {
+                type_name: std::any::type_name::<C>(),
+                decode: decode::<C>,
+            },
+        );
+        drop(lock);
+        self.command_encoders.rcu(|current| {
+            let mut encoders = CommandEncoders::clone(current);
+            encoders.insert(TypeId::of::<C>(), (name, encode_message::<C>));
+            encoders
+        });
+
+        self
+    }
+
+    /// Allows the query to be dispatched by its message name.
+    /// `V` is the type of the value the query's handler sets
+    pub async fn register_dynamic_query<Q, V>(&self) -> &Self
+    where
+        Q: SerializableMessage,
+        V: Serialize + Send + Sync + 'static,
+    {
+        let name = Q::message_name();
+        let mut lock = self.dynamic.write().await;
+
+        if let Some(existing) = lock.queries.get(name)
+            && existing.type_name != std::any::type_name::<Q>()
+        {
+            tracing::error!(target: LOG_TARGET, "message name {} is already used by {}", name, existing.type_name);
+            panic!(
+                "message name {} is already used by {}",
+                name, existing.type_name
+            );
+        }
+
+        tracing::debug!(target: LOG_TARGET, "registered dynamic query {:?} for {:?}", name, std::any::type_name::<Q>());
+        lock.queries.insert(
+            name,
+            DynamicQuery {
+                type_name: std::any::type_name::<Q>(),
+                decode: decode::<Q>,
+                encode_value: encode_value::<V>,
+            },
+        );
+
+        self
+    }
+
+    /// Checks if a command can be dispatched by this name
+    pub async fn has_dynamic_command(&self, name: &str) -> bool {
+        self.dynamic.read().await.commands.contains_key(name)
+    }
+
+    /// Checks if a query can be dispatched by this name
+    pub async fn has_dynamic_query(&self, name: &str) -> bool {
+        self.dynamic.read().await.queries.contains_key(name)
+    }
+
+    /// Deserializes the command registered under `name` and dispatches it.
+    /// Returns true if a handler handled the command, even when the handler
+    /// failed, like `Busstop::dispatch_command`
+    pub async fn dispatch_command_dynamic(
+        &self,
+        name: &str,
+        bytes: &[u8],
+        codec: &dyn Codec,
+    ) -> Result<bool, DynamicDispatchError> {
+        let dispatched = self.decode_command(name, bytes, codec).await?;
+
+        Ok(self.route_command(dispatched).await.handled())
+    }
+
+    /// Deserializes the query registered under `name` and dispatches it.
+    /// Returns the serialized value of the query, if one was set
+    pub async fn dispatch_query_dynamic(
+        &self,
+        name: &str,
+        bytes: &[u8],
+        codec: &dyn Codec,
+    ) -> Result<Option<Vec<u8>>, DynamicDispatchError> {
+        let (dispatched, encode_value) = self.decode_query(name, bytes, codec).await?;
+        let dispatched = self.route_query(dispatched).await;
+
+        Ok(encode_value(codec, &dispatched)?)
+    }
+
+    /// Dispatches the command or query in the envelope and returns the reply
+    ///
+    /// The reply is encoded with the first codec the sender accepts
+    /// that is also available locally
+    ///
+    /// The principal in the metadata of the envelope is dropped, callers
+    /// cannot choose who they dispatch as
+    pub async fn dispatch_envelope(&self, envelope: Envelope, codecs: &Codecs) -> Envelope {
+        self.dispatch_envelope_as(envelope, codecs, None).await
+    }
+
+    /// Dispatches the envelope as the principal the caller was authenticated as
+    pub(crate) async fn dispatch_envelope_as(
+        &self,
+        mut envelope: Envelope,
+        codecs: &Codecs,
+        principal: Option<String>,
+    ) -> Envelope {
+        envelope.metadata = crate::authorization::untrusted(std::mem::take(&mut envelope.metadata));
+        if let Some(principal) = principal {
+            envelope
+                .metadata
+                .insert(crate::PRINCIPAL_METADATA_KEY, principal);
+        }
+
+        let reply_codec = match codecs.negotiate(&envelope.accept) {
+            Ok(codec) => codec,
+            Err(e) => {
+                tracing::error!(target: LOG_TARGET, "no codec available to reply to {:?}: {}", envelope.name, e);
+                return Envelope::reply_to(&envelope, envelope.codec.as_str(), Vec::new())
+                    .with_error(DispatchError::Failed(e.to_string()));
+            }
+        };
+
+        let result = match envelope.kind {
+            EnvelopeKind::Command => self.dispatch_command_envelope(&envelope, codecs).await,
+            EnvelopeKind::Query => {
+                self.dispatch_query_envelope(&envelope, codecs, reply_codec.as_ref())
+                    .await
+            }
+            EnvelopeKind::Reply => Err(DynamicDispatchError::Codec(CodecError::new(
+                "a reply cannot be dispatched",
+            ))),
+        };
+
+        let reply = Envelope::reply_to(&envelope, reply_codec.name(), Vec::new());
+        match result {
+            Ok((handled, error, payload)) => {
+                let mut reply = reply.with_handled(handled).with_payload(payload);
+                reply.error = error;
+                reply
+            }
+            Err(DynamicDispatchError::UnknownMessage(_)) => reply,
+            Err(e) => reply.with_error(DispatchError::Failed(e.to_string())),
+        }
+    }
+
+    async fn dispatch_command_envelope(
+        &self,
+        envelope: &Envelope,
+        codecs: &Codecs,
+    ) -> Result<EnvelopeResult, DynamicDispatchError> {
+        let codec = envelope.payload_codec(codecs)?;
+        let dispatched = self
+            .decode_command(&envelope.name, &envelope.payload, codec.as_ref())
+            .await?
+            .with_metadata(envelope.metadata.clone());
+        let result = self.route_command(dispatched).await;
+
+        Ok((result.handled(), result.error().cloned(), Vec::new()))
+    }
+
+    async fn dispatch_query_envelope(
+        &self,
+        envelope: &Envelope,
+        codecs: &Codecs,
+        reply_codec: &dyn Codec,
+    ) -> Result<EnvelopeResult, DynamicDispatchError> {
+        let codec = envelope.payload_codec(codecs)?;
+        let (dispatched, encode_value) = self
+            .decode_query(&envelope.name, &envelope.payload, codec.as_ref())
+            .await?;
+        let result = self
+            .route_query(dispatched.with_metadata(envelope.metadata.clone()))
+            .await;
+        let payload = encode_value(reply_codec, &result)?.unwrap_or_default();
+
+        Ok((result.handled(), result.error().cloned(), payload))
+    }
+
+    /// The message name and the encoder of a command registered
+    /// with `register_dynamic_command`
+    pub(crate) fn command_encoder(
+        &self,
+        type_id: TypeId,
+    ) -> Option<(&'static str, EncodeMessageFn)> {
+        self.command_encoders.load().get(&type_id).copied()
+    }
+
+    pub(crate) async fn decode_command(
+        &self,
+        name: &str,
+        bytes: &[u8],
+        codec: &dyn Codec,
+    ) -> Result<DispatchedCommand, DynamicDispatchError> {
+        let entry = self.dynamic.read().await.commands.get(name).copied();
+        let Some(entry) = entry else {
+            tracing::debug!(target: LOG_TARGET, "no dynamic command registered as {:?}", name);
+            return Err(DynamicDispatchError::UnknownMessage(name.to_string()));
+        };
+
+        let command = (entry.decode)(codec, bytes)?;
+
+        tracing::debug!(target: LOG_TARGET, "dispatching dynamic command: {:?}", entry.type_name);
+        Ok(DispatchedCommand::new(command, entry.type_name))
+    }
+
+    pub(crate) async fn decode_query(
+        &self,
+        name: &str,
+        bytes: &[u8],
+        codec: &dyn Codec,
+    ) -> Result<(DispatchedQuery, EncodeValueFn), DynamicDispatchError> {
+        let entry = self.dynamic.read().await.queries.get(name).copied();
+        let Some(entry) = entry else {
+            tracing::debug!(target: LOG_TARGET, "no dynamic query registered as {:?}", name);
+            return Err(DynamicDispatchError::UnknownMessage(name.to_string()));
+        };
+
+        let query = (entry.decode)(codec, bytes)?;
+
+        tracing::debug!(target: LOG_TARGET, "dispatching dynamic query: {:?}", entry.type_name);
+        Ok((
+            DispatchedQuery::new(query, entry.type_name),
+            entry.encode_value,
+        ))
+    }
+}
+
+#[cfg(all(test, feature = "json"))]
+mod test {
+    use serde::Deserialize;
+
+    use super::*;
+    use crate::{DispatchableCommand, DispatchableQuery};
+
+    #[derive(Serialize, Deserialize)]
+    struct RenameUser {
+        name: String,
+    }
+    impl DispatchableCommand for RenameUser {}
+    impl SerializableMessage for RenameUser {
+        fn message_name() -> &'static str {
+            "rename_user"
+        }
+    }
+
+    #[derive(Serialize, Deserialize)]
+    struct ShredFile {
+        path: String,
+    }
+    impl DispatchableCommand for ShredFile {}
+    impl SerializableMessage for ShredFile {}
+
+    #[derive(Serialize, Deserialize)]
+    struct Multiply(i32, i32);
+    impl DispatchableQuery for Multiply {}
+    impl SerializableMessage for Multiply {}
+
+    #[tokio::test]
+    async fn test_failed_dynamic_command_is_handled() {
+        struct Shredder;
+
+        #[async_trait::async_trait]
+        impl crate::CommandHandler for Shredder {
+            async fn handle_command(&self, mut c: DispatchedCommand) -> DispatchedCommand {
+                let path = c.the_command::<ShredFile>().map(|c| c.path.clone());
+                c.fail(crate::DispatchError::Failed(format!(
+                    "{:?} is read only",
+                    path
+                )));
+                c
+            }
+        }
+
+        let bus = Busstop::new();
+        bus.register_command::<ShredFile>(Shredder)
+            .await
+            .register_dynamic_command::<ShredFile>()
+            .await;
+
+        let name = ShredFile::message_name();
+        let handled = bus
+            .dispatch_command_dynamic(name, br#"{"path":"/etc"}"#, &JsonCodec)
+            .await;
+        assert_eq!(handled, Ok(true));
+        assert!(
+            bus.dispatch_command(ShredFile {
+                path: "/etc".to_string()
+            })
+            .await
+        );
+    }
+
+    #[tokio::test]
+    async fn test_dispatch_command_dynamic() {
+        let bus = Busstop::instance();
+        bus.register_command_fn::<RenameUser, _>(|cmd: RenameUser| async move {
+            assert_eq!(cmd.name, "james");
+        })
+        .await;
+        bus.register_dynamic_command::<RenameUser>().await;
+
+        let handled = bus
+            .dispatch_command_dynamic("rename_user", br#"{"name":"james"}"#, &JsonCodec)
+            .await;
+        assert_eq!(handled, Ok(true));
+
+        let result = bus
+            .dispatch_command_dynamic("rename_user", b"[]", &JsonCodec)
+            .await;
+        assert!(matches!(result, Err(DynamicDispatchError::Codec(_))));
+
+        let result = bus
+            .dispatch_command_dynamic("unknown", b"{}", &JsonCodec)
+            .await;
+        assert_eq!(
+            result,
+            Err(DynamicDispatchError::UnknownMessage("unknown".to_string()))
+        );
+    }
+
+    #[tokio::test]
+    async fn test_dispatch_query_dynamic() {
+        let bus = Busstop::instance();
+        bus.register_query_fn::<Multiply, _>(|q: &Multiply| {
+            let ans = q.0 * q.1;
+            async move { ans }
+        })
+        .await;
+        bus.register_dynamic_query::<Multiply, i32>().await;
+
+        let value = bus
+            .dispatch_query_dynamic(Multiply::message_name(), b"[6,7]", &JsonCodec)
+            .await;
+
+        assert_eq!(value, Ok(Some(b"42".to_vec())));
+    }
+}
@@ -0,0 +1,257 @@
+use std::{fmt::Display, sync::Arc};
+
+use serde::{Serialize, de::DeserializeOwned};
+
+/// Erased deserializer handed to the callback of [`Codec::decode`]
+pub type ErasedDeserializer<'a, 'de> = &'a mut dyn erased_serde::Deserializer<'de>;
+
+/// Callback used by [`Codec::decode`]
+pub type DecodeVisitor<'a> =
+    &'a mut dyn for<'b, 'de> FnMut(ErasedDeserializer<'b, 'de>) -> Result<(), erased_serde::Error>;
+
+/// Turns messages and values into bytes and back
+///
+/// The trait is object safe so that the codec can be picked at runtime.
+/// Use `encode_value` and `decode_value` for typed access
+pub trait Codec: Send + Sync {
+    /// The name of the codec
+    fn name(&self) -> &'static str;
+
+    /// Serializes the value
+    fn encode(&self, value: &dyn erased_serde::Serialize) -> Result<Vec<u8>, CodecError>;
+
+    /// Creates a deserializer over the bytes and hands it to the visitor
+    fn decode(&self, bytes: &[u8], visitor: DecodeVisitor<'_>) -> Result<(), CodecError>;
+}
+
+impl dyn Codec + '_ {
+    /// Serializes a typed value
+    pub fn encode_value<T: Serialize>(&self, value: &T) -> Result<Vec<u8>, CodecError> {
+        self.encode(value)
+    }
+
+    /// Deserializes the bytes into a typed value
+    pub fn decode_value<T: DeserializeOwned>(&self, bytes: &[u8]) -> Result<T, CodecError> {
+        let mut value = None;
+        self.decode(bytes, &mut |deserializer| {
+            value = Some(erased_serde::deserialize(deserializer)?);
+            Ok(())
+        })?;
+
+        value.ok_or_else(|| CodecError::new("codec did not produce a value"))
+    }
+}
+
+/// Error returned when encoding or decoding fails
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct CodecError {
+    message: String,
+}
+
+impl CodecError {
+    /// Create a new instance
+    pub fn new(message: impl Display) -> Self {
+        Self {
+            message: message.to_string(),
+        }
+    }
+
+    /// The error message
+    pub fn message(&self) -> &str {
+        &self.message
+    }
+}
+
+impl Display for CodecError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "codec error: {}", self.message)
+    }
+}
+
+impl std::error::Error for CodecError {}
+
+/// JSON codec
+#[cfg(feature = "json")]
+#[derive(Debug, Default, Clone, Copy)]
+pub struct JsonCodec;
+
+#[cfg(feature = "json")]
+impl Codec for JsonCodec {
+    fn name(&self) -> &'static str {
+        "json"
+    }
+
+    fn encode(&self, value: &dyn erased_serde::Serialize) -> Result<Vec<u8>, CodecError> {
+        let mut bytes = Vec::new();
+        erased_serde::serialize(value, &mut serde_json::Serializer::new(&mut bytes))
+            .map_err(CodecError::new)?;
+
+        Ok(bytes)
+    }
+
+    fn decode(&self, bytes: &[u8], visitor: DecodeVisitor<'_>) -> Result<(), CodecError> {
+        let mut deserializer = serde_json::Deserializer::from_slice(bytes);
+        visitor(&mut <dyn erased_serde::Deserializer>::erase(
+            &mut deserializer,
+        ))
+        .map_err(CodecError::new)?;
+
+        deserializer.end().map_err(CodecError::new)
+    }
+}
+
+/// MessagePack codec
+///
+/// Structs are encoded as maps so that peers with different versions
+/// of a message can still read it
+#[cfg(feature = "msgpack")]
+#[derive(Debug, Default, Clone, Copy)]
+pub struct MsgPackCodec;
+
+#[cfg(feature = "msgpack")]
+impl Codec for MsgPackCodec {
+    fn name(&self) -> &'static str {
+        "msgpack"
+    }
+
+    fn encode(&self, value: &dyn erased_serde::Serialize) -> Result<Vec<u8>, CodecError> {
+        let mut bytes = Vec::new();
+        erased_serde::serialize(
+            value,
+            &mut rmp_serde::Serializer::new(&mut bytes).with_struct_map(),
+        )
+        .map_err(CodecError::new)?;
+
+        Ok(bytes)
+    }
+
+    fn decode(&self, bytes: &[u8], visitor: DecodeVisitor<'_>) -> Result<(), CodecError> {
+        let mut remaining = bytes;
+        visitor(&mut <dyn erased_serde::Deserializer>::erase(
+            &mut rmp_serde::Deserializer::new(&mut remaining),
+        ))
+        .map_err(CodecError::new)?;
+
+        end(remaining)
+    }
+}
+
+/// bincode codec
+#[cfg(feature = "bincode")]
+#[derive(Debug, Default, Clone, Copy)]
+pub struct BincodeCodec;
+
+#[cfg(feature = "bincode")]
+impl Codec for BincodeCodec {
+    fn name(&self) -> &'static str {
+        "bincode"
+    }
+
+    fn encode(&self, value: &dyn erased_serde::Serialize) -> Result<Vec<u8>, CodecError> {
+        let mut bytes = Vec::new();
+        erased_serde::serialize(
+            value,
+            &mut bincode::Serializer::new(&mut bytes, bincode::DefaultOptions::new()),
+        )
+        .map_err(CodecError::new)?;
+
+        Ok(bytes)
+    }
+
+    fn decode(&self, bytes: &[u8], visitor: DecodeVisitor<'_>) -> Result<(), CodecError> {
+        let mut remaining = bytes;
+        visitor(&mut <dyn erased_serde::Deserializer>::erase(
+            &mut bincode::Deserializer::with_reader(&mut remaining, bincode::DefaultOptions::new()),
+        ))
+        .map_err(CodecError::new)?;
+
+        end(remaining)
+    }
+}
+
+/// Fails when the bytes hold more than the decoded value
+#[cfg(any(feature = "msgpack", feature = "bincode"))]
+fn end(remaining: &[u8]) -> Result<(), CodecError> {
+    if remaining.is_empty() {
+        Ok(())
+    } else {
+        Err(CodecError::new(format!(
+            "{} trailing bytes after the value",
+            remaining.len()
+        )))
+    }
+}
+
+/// The codecs available to this process, in order of preference
+#[derive(Clone)]
+pub struct Codecs {
+    list: Vec<Arc<dyn Codec>>,
+}
+
+impl Codecs {
+    /// Create an empty list
+    pub fn new() -> Self {
+        Self { list: Vec::new() }
+    }
+
+    /// Adds the codec. Codecs added first are preferred
+    pub fn with(mut self, codec: impl Codec + 'static) -> Self {
+        self.list.push(Arc::new(codec));
+        self
+    }
+
+    /// Returns the codec with this name
+    pub fn get(&self, name: &str) -> Option<Arc<dyn Codec>> {
+        self.list.iter().find(|c| c.name() == name).cloned()
+    }
+
+    /// The most preferred codec
+    pub fn preferred(&self) -> Option<Arc<dyn Codec>> {
+        self.list.first().cloned()
+    }
+
+    /// The names of the codecs, in order of preference
+    pub fn names(&self) -> Vec<String> {
+        self.list.iter().map(|c| c.name().to_string()).collect()
+    }
+
+    /// Picks the first codec in `accepted` that is available locally.
+    /// The preferred codec is picked when `accepted` is empty. Fails when
+    /// none of the accepted codecs is available
+    pub fn negotiate<S: AsRef<str>>(&self, accepted: &[S]) -> Result<Arc<dyn Codec>, CodecError> {
+        if accepted.is_empty() {
+            return self
+                .preferred()
+                .ok_or_else(|| CodecError::new("no codec available"));
+        }
+
+        accepted
+            .iter()
+            .find_map(|name| self.get(name.as_ref()))
+            .ok_or_else(|| {
+                let accepted = accepted.iter().map(AsRef::as_ref).collect::<Vec<_>>();
+                CodecError::new(format!("none of the codecs {:?} is available", accepted))
+            })
+    }
+}
+
+impl Default for Codecs {
+    /// All the codecs enabled via cargo features
+    #[allow(unused_mut)]
+    fn default() -> Self {
+        let mut codecs = Self::new();
+        #[cfg(feature = "json")]
+        {
+            codecs = codecs.with(JsonCodec);
+        }
+        #[cfg(feature = "msgpack")]
+        {
+            codecs = codecs.with(MsgPackCodec);
+        }
+        #[cfg(feature = "bincode")]
+        {
+            codecs = codecs.with(BincodeCodec);
+        }
+        codecs
+    }
+}
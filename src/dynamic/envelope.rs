@@ -0,0 +1,259 @@
+use serde::{Deserialize, Serialize};
+
+use super::{Codec, CodecError, Codecs, SerializableMessage};
+use crate::{DispatchError, Metadata};
+
+/// What an envelope carries
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub enum EnvelopeKind {
+    /// A command to dispatch
+    Command,
+    /// A query to dispatch
+    Query,
+    /// The reply to a command or a query
+    Reply,
+}
+
+/// A serialized message along with the information needed to dispatch it
+/// in another process
+///
+/// The envelope records the codec used for the payload and the codecs the
+/// sender is able to decode, so that peers running different versions can
+/// agree on a format for the reply.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Envelope {
+    /// Identifies the request. A reply carries the id of its request
+    pub id: u64,
+    /// What the envelope carries
+    pub kind: EnvelopeKind,
+    /// The message name
+    pub name: String,
+    /// The name of the codec used to encode the payload
+    pub codec: String,
+    /// The codecs the sender can decode, in order of preference
+    pub accept: Vec<String>,
+    /// Metadata passed along with the message
+    pub metadata: Metadata,
+    /// The encoded message or value
+    pub payload: Vec<u8>,
+    /// Replies only. True if the message was handled
+    pub handled: bool,
+    /// Replies only. The reason the message failed
+    pub error: Option<DispatchError>,
+}
+
+impl Envelope {
+    /// Create a new instance
+    pub fn new(kind: EnvelopeKind, name: &str, codec: &dyn Codec, payload: Vec<u8>) -> Self {
+        Self {
+            id: 0,
+            kind,
+            name: name.to_string(),
+            codec: codec.name().to_string(),
+            accept: vec![codec.name().to_string()],
+            metadata: Metadata::new(),
+            payload,
+            handled: false,
+            error: None,
+        }
+    }
+
+    /// Creates an empty reply to the request
+    pub fn reply_to(request: &Envelope, codec: &str, payload: Vec<u8>) -> Self {
+        Self {
+            id: request.id,
+            kind: EnvelopeKind::Reply,
+            name: request.name.clone(),
+            codec: codec.to_string(),
+            accept: Vec::new(),
+            metadata: Metadata::new(),
+            payload,
+            handled: false,
+            error: None,
+        }
+    }
+
+    /// Wraps the command in an envelope
+    pub fn for_command<C: SerializableMessage>(
+        command: &C,
+        codec: &dyn Codec,
+    ) -> Result<Self, CodecError> {
+        Ok(Self::new(
+            EnvelopeKind::Command,
+            C::message_name(),
+            codec,
+            codec.encode_value(command)?,
+        ))
+    }
+
+    /// Wraps the query in an envelope
+    pub fn for_query<Q: SerializableMessage>(
+        query: &Q,
+        codec: &dyn Codec,
+    ) -> Result<Self, CodecError> {
+        Ok(Self::new(
+            EnvelopeKind::Query,
+            Q::message_name(),
+            codec,
+            codec.encode_value(query)?,
+        ))
+    }
+
+    /// Sets the request id
+    pub fn with_id(mut self, id: u64) -> Self {
+        self.id = id;
+        self
+    }
+
+    /// Sets the metadata
+    pub fn with_metadata(mut self, metadata: Metadata) -> Self {
+        self.metadata = metadata;
+        self
+    }
+
+    /// Sets the payload
+    pub fn with_payload(mut self, payload: Vec<u8>) -> Self {
+        self.payload = payload;
+        self
+    }
+
+    /// Replies only. Sets whether the message was handled
+    pub fn with_handled(mut self, handled: bool) -> Self {
+        self.handled = handled;
+        self
+    }
+
+    /// Replies only. Sets the reason the message failed
+    pub fn with_error(mut self, error: DispatchError) -> Self {
+        self.error = Some(error);
+        self
+    }
+
+    /// Sets the codecs the sender can decode
+    pub fn with_accept(mut self, codecs: &Codecs) -> Self {
+        self.accept = codecs.names();
+        self
+    }
+
+    /// Decodes the payload with the codec recorded in the envelope
+    pub fn decode_payload<T: serde::de::DeserializeOwned>(
+        &self,
+        codecs: &Codecs,
+    ) -> Result<T, CodecError> {
+        self.payload_codec(codecs)?.decode_value(&self.payload)
+    }
+
+    /// Returns the codec recorded in the envelope
+    pub fn payload_codec(&self, codecs: &Codecs) -> Result<std::sync::Arc<dyn Codec>, CodecError> {
+        codecs
+            .get(&self.codec)
+            .ok_or_else(|| CodecError::new(format!("codec {} is not available", self.codec)))
+    }
+
+    /// Serializes the envelope. The name of the codec is written in
+    /// front of the encoded envelope so that the receiver knows how to read it
+    pub fn to_bytes(&self, codec: &dyn Codec) -> Result<Vec<u8>, CodecError> {
+        let name = codec.name().as_bytes();
+        let Ok(len) = u8::try_from(name.len()) else {
+            return Err(CodecError::new("codec name is too long"));
+        };
+
+        let mut bytes = vec![len];
+        bytes.extend_from_slice(name);
+        bytes.extend(codec.encode_value(self)?);
+
+        Ok(bytes)
+    }
+
+    /// Deserializes an envelope written by `to_bytes`
+    pub fn from_bytes(bytes: &[u8], codecs: &Codecs) -> Result<Self, CodecError> {
+        let Some((len, rest)) = bytes.split_first() else {
+            return Err(CodecError::new("empty envelope"));
+        };
+        let len = *len as usize;
+        if rest.len() < len {
+            return Err(CodecError::new("truncated envelope"));
+        }
+
+        let name = std::str::from_utf8(&rest[..len]).map_err(CodecError::new)?;
+        let codec = codecs
+            .get(name)
+            .ok_or_else(|| CodecError::new(format!("codec {} is not available", name)))?;
+
+        codec.decode_value(&rest[len..])
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[derive(Debug, PartialEq, Serialize, Deserialize)]
+    struct Ping {
+        count: u32,
+        label: Option<String>,
+    }
+    impl SerializableMessage for Ping {}
+
+    #[test]
+    fn test_envelope_round_trip() {
+        let codecs = Codecs::default();
+        let ping = Ping {
+            count: 3,
+            label: Some("hello".to_string()),
+        };
+
+        for codec in codecs.names() {
+            let codec = codecs.get(&codec).unwrap();
+            let envelope = Envelope::for_command(&ping, codec.as_ref())
+                .unwrap()
+                .with_id(7)
+                .with_accept(&codecs)
+                .with_metadata(Metadata::from_iter([("user", "james")]));
+
+            let bytes = envelope.to_bytes(codec.as_ref()).unwrap();
+            let decoded = Envelope::from_bytes(&bytes, &codecs).unwrap();
+
+            assert_eq!(decoded, envelope);
+            assert_eq!(decoded.decode_payload::<Ping>(&codecs).unwrap(), ping);
+        }
+    }
+
+    #[test]
+    fn test_trailing_bytes_are_rejected() {
+        let codecs = Codecs::default();
+        let ping = Ping {
+            count: 3,
+            label: None,
+        };
+
+        for codec in codecs.names() {
+            let codec = codecs.get(&codec).unwrap();
+            let mut bytes = codec.encode_value(&ping).unwrap();
+            assert_eq!(codec.decode_value::<Ping>(&bytes).unwrap(), ping);
+
+            bytes.push(0);
+            assert!(
+                codec.decode_value::<Ping>(&bytes).is_err(),
+                "{}",
+                codec.name()
+            );
+        }
+    }
+
+    #[test]
+    fn test_negotiate_codec() {
+        let codecs = Codecs::default();
+        let Some(preferred) = codecs.preferred() else {
+            return;
+        };
+
+        let picked = codecs.negotiate(&["unknown", preferred.name()]).unwrap();
+        assert_eq!(picked.name(), preferred.name());
+
+        let picked = codecs.negotiate::<&str>(&[]).unwrap();
+        assert_eq!(picked.name(), preferred.name());
+
+        assert!(codecs.negotiate(&["unknown"]).is_err());
+    }
+}
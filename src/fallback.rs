@@ -0,0 +1,239 @@
+use std::sync::Arc;
+
+use crate::{
+    Busstop, CommandHandler, QueryHandler, command::CommandHandlerManager,
+    query::QueryHandlerManager,
+};
+
+const LOG_TARGET: &str = "bus_stop";
+
+/// The fallback handlers, longest prefix first
+#[derive(Default)]
+pub(crate) struct Fallbacks {
+    commands: Vec<(String, Arc<CommandHandlerManager>)>,
+    queries: Vec<(String, Arc<QueryHandlerManager>)>,
+}
+
+impl Fallbacks {
+    pub(crate) fn command(&self, name: &str) -> Option<Arc<CommandHandlerManager>> {
+        find(&self.commands, name)
+    }
+
+    pub(crate) fn query(&self, name: &str) -> Option<Arc<QueryHandlerManager>> {
+        find(&self.queries, name)
+    }
+}
+
+fn find<T>(fallbacks: &[(String, Arc<T>)], name: &str) -> Option<Arc<T>> {
+    fallbacks
+        .iter()
+        .find(|(prefix, _)| covers(prefix, name))
+        .map(|(_, manager)| Arc::clone(manager))
+}
+
+/// A prefix covers the names in its module path. "app::billing" covers
+/// "app::billing::Charge" but not "app::billing_v2::Charge"
+fn covers(prefix: &str, name: &str) -> bool {
+    match name.strip_prefix(prefix) {
+        Some(rest) => {
+            prefix.is_empty() || prefix.ends_with("::") || rest.is_empty() || rest.starts_with("::")
+        }
+        None => false,
+    }
+}
+
+fn insert<T>(
+    fallbacks: &[(String, Arc<T>)],
+    prefix: &str,
+    manager: &Arc<T>,
+) -> Vec<(String, Arc<T>)> {
+    let mut fallbacks = remove(fallbacks, prefix);
+    fallbacks.push((prefix.to_string(), Arc::clone(manager)));
+    fallbacks.sort_by_key(|(prefix, _)| std::cmp::Reverse(prefix.len()));
+    fallbacks
+}
+
+fn remove<T>(fallbacks: &[(String, Arc<T>)], prefix: &str) -> Vec<(String, Arc<T>)> {
+    fallbacks
+        .iter()
+        .filter(|(existing, _)| existing != prefix)
+        .cloned()
+        .collect()
+}
+
+impl Busstop {
+    /// Register the handler of every command that does not have a handler.
+    /// The handler can use `DispatchedCommand::name` to tell the commands apart
+    pub async fn register_command_fallback(&self, handler: impl CommandHandler + 'static) -> &Self {
+        self.register_command_fallback_for("", handler).await
+    }
+
+    /// Register the handler of the commands in the module path `prefix`
+    /// that do not have a handler. The fallback with the longest prefix is used.
+    /// Replaces the fallback already registered for the prefix
+    ///
+    /// ```rust,ignore
+    /// bus.register_command_fallback_for("my_app::billing", ForwardToBilling).await;
+    /// ```
+    pub async fn register_command_fallback_for(
+        &self,
+        prefix: &str,
+        handler: impl CommandHandler + 'static,
+    ) -> &Self {
+        tracing::debug!(target: LOG_TARGET, "registered command fallback {:?} for {:?}", handler.command_handler_name(), prefix);
+        let manager = Arc::new(CommandHandlerManager::new(handler).await);
+        self.fallbacks.rcu(|current| Fallbacks {
+            commands: insert(&current.commands, prefix, &manager),
+            queries: current.queries.clone(),
+        });
+
+        self
+    }
+
+    /// Removes the command fallback registered for the prefix
+    pub async fn unregister_command_fallback(&self, prefix: &str) -> &Self {
+        self.fallbacks.rcu(|current| Fallbacks {
+            commands: remove(&current.commands, prefix),
+            queries: current.queries.clone(),
+        });
+
+        self
+    }
+
+    /// Register the handler of every query that does not have a handler.
+    /// The handler can use `DispatchedQuery::name` to tell the queries apart
+    pub async fn register_query_fallback(&self, handler: impl QueryHandler + 'static) -> &Self {
+        self.register_query_fallback_for("", handler).await
+    }
+
+    /// Register the handler of the queries in the module path `prefix`
+    /// that do not have a handler.
+    ///
+    /// See [`Busstop::register_command_fallback_for`]
+    pub async fn register_query_fallback_for(
+        &self,
+        prefix: &str,
+        handler: impl QueryHandler + 'static,
+    ) -> &Self {
+        tracing::debug!(target: LOG_TARGET, "registered query fallback {:?} for {:?}", handler.query_handler_name(), prefix);
+        let manager = Arc::new(QueryHandlerManager::new(handler).await);
+        self.fallbacks.rcu(|current| Fallbacks {
+            commands: current.commands.clone(),
+            queries: insert(&current.queries, prefix, &manager),
+        });
+
+        self
+    }
+
+    /// Removes the query fallback registered for the prefix
+    pub async fn unregister_query_fallback(&self, prefix: &str) -> &Self {
+        self.fallbacks.rcu(|current| Fallbacks {
+            commands: current.commands.clone(),
+            queries: remove(&current.queries, prefix),
+        });
+
+        self
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::sync::Mutex;
+
+    use super::*;
+    use crate::{CommandFnHandler, DispatchedCommand, DispatchedQuery};
+
+    mod billing {
+        pub struct Charge;
+        pub struct Refund;
+    }
+
+    mod billing_v2 {
+        pub struct Charge;
+    }
+
+    struct Unknown;
+
+    struct Recorder(Arc<Mutex<Vec<String>>>);
+
+    #[async_trait::async_trait]
+    impl CommandHandler for Recorder {
+        async fn handle_command(&self, dispatched: DispatchedCommand) -> DispatchedCommand {
+            self.0.lock().unwrap().push(dispatched.name().to_string());
+            dispatched
+        }
+    }
+
+    struct Echo;
+
+    #[async_trait::async_trait]
+    impl QueryHandler for Echo {
+        async fn handle_query(&self, dispatched: DispatchedQuery) -> DispatchedQuery {
+            dispatched.set_value(dispatched.name());
+            dispatched
+        }
+    }
+
+    #[test]
+    fn test_prefix_covers_module_path() {
+        assert!(covers("", "app::Charge"));
+        assert!(covers("app::billing", "app::billing::Charge"));
+        assert!(covers("app::billing::", "app::billing::Charge"));
+        assert!(!covers("app::billing", "app::billing_v2::Charge"));
+        assert!(!covers("app::billing", "app::Charge"));
+    }
+
+    #[tokio::test]
+    async fn test_command_fallbacks() {
+        let everything = Arc::new(Mutex::new(Vec::new()));
+        let billing = Arc::new(Mutex::new(Vec::new()));
+        let prefix = std::any::type_name::<billing::Charge>()
+            .trim_end_matches("::Charge")
+            .to_string();
+
+        let bus = Busstop::new();
+        bus.register_command_fn::<billing::Refund, _>(|_: billing::Refund| async {})
+            .await
+            .register_command_fallback(Recorder(everything.clone()))
+            .await
+            .register_command_fallback_for(&prefix, Recorder(billing.clone()))
+            .await;
+
+        assert!(bus.dispatch_command(billing::Charge).await);
+        assert!(bus.dispatch_command(billing::Refund).await);
+        assert!(bus.dispatch_command(billing_v2::Charge).await);
+        assert!(bus.dispatch_command(Unknown).await);
+
+        assert_eq!(
+            *billing.lock().unwrap(),
+            vec![std::any::type_name::<billing::Charge>()]
+        );
+        assert_eq!(
+            *everything.lock().unwrap(),
+            vec![
+                std::any::type_name::<billing_v2::Charge>(),
+                std::any::type_name::<Unknown>()
+            ]
+        );
+
+        bus.unregister_command_fallback("").await;
+        assert!(!bus.dispatch_command(Unknown).await);
+        bus.register_command::<Unknown>(CommandFnHandler::new(|_: Unknown| async {}))
+            .await;
+        assert!(bus.dispatch_command(Unknown).await);
+    }
+
+    #[tokio::test]
+    async fn test_query_fallback() {
+        let bus = Busstop::new();
+        assert!(!bus.dispatch_query(Unknown).await.handled());
+
+        bus.register_query_fallback(Echo).await;
+        let result = bus.dispatch_query(Unknown).await;
+        assert!(result.handled());
+        assert_eq!(
+            result.value::<&str>(),
+            Some(&std::any::type_name::<Unknown>())
+        );
+    }
+}
@@ -0,0 +1,294 @@
+use std::{
+    any::{Any, TypeId},
+    collections::HashMap,
+    sync::Arc,
+};
+
+use crate::{
+    Busstop, CommandHandler, QueryHandler, command::CommandHandlerManager,
+    query::QueryHandlerManager,
+};
+
+const LOG_TARGET: &str = "bus_stop";
+
+/// The families a message belongs to, in the order it joined them,
+/// along with the cast from the message to each family's trait object
+pub(crate) type Memberships = Arc<Vec<(TypeId, Arc<dyn Any + Send + Sync>)>>;
+
+type CastFn<T> = dyn for<'a> Fn(&'a (dyn Any + Send + Sync)) -> Option<&'a T> + Send + Sync;
+
+struct Caster<T: ?Sized + 'static>(Box<CastFn<T>>);
+
+/// The family handlers, keyed by the type id of the trait object
+#[derive(Default, Clone)]
+pub(crate) struct Families {
+    commands: HashMap<TypeId, Arc<CommandHandlerManager>>,
+    queries: HashMap<TypeId, Arc<QueryHandlerManager>>,
+    members: HashMap<TypeId, Memberships>,
+}
+
+impl Families {
+    /// The handler of the first family of the command that has one
+    pub(crate) fn command(
+        &self,
+        message: TypeId,
+    ) -> Option<(Arc<CommandHandlerManager>, Memberships)> {
+        let memberships = self.members.get(&message)?;
+        memberships.iter().find_map(|(family, _)| {
+            self.commands
+                .get(family)
+                .map(|manager| (Arc::clone(manager), Arc::clone(memberships)))
+        })
+    }
+
+    /// The handler of the first family of the query that has one
+    pub(crate) fn query(&self, message: TypeId) -> Option<(Arc<QueryHandlerManager>, Memberships)> {
+        let memberships = self.members.get(&message)?;
+        memberships.iter().find_map(|(family, _)| {
+            self.queries
+                .get(family)
+                .map(|manager| (Arc::clone(manager), Arc::clone(memberships)))
+        })
+    }
+}
+
+/// Casts the message to the trait object `T` when the message is a member of the family
+pub(crate) fn cast<'a, T: ?Sized + 'static>(
+    memberships: &Memberships,
+    message: &'a (dyn Any + Send + Sync),
+) -> Option<&'a T> {
+    let (_, caster) = memberships
+        .iter()
+        .find(|(family, _)| *family == TypeId::of::<T>())?;
+
+    (caster.downcast_ref::<Caster<T>>()?.0)(message)
+}
+
+impl Busstop {
+    /// Declares that the message belongs to the family of the trait object `T`.
+    /// The cast turns the message into the trait object, usually `|m| m`
+    ///
+    /// ```rust,ignore
+    /// bus.add_to_family::<CreateUser, dyn Auditable>(|c| c).await;
+    /// ```
+    pub async fn add_to_family<M, T>(&self, cast: fn(&M) -> &T) -> &Self
+    where
+        M: Send + Sync + 'static,
+        T: ?Sized + 'static,
+    {
+        let caster: Arc<dyn Any + Send + Sync> = Arc::new(Caster::<T>(Box::new(move |message| {
+            message.downcast_ref::<M>().map(cast)
+        })));
+        self.families.rcu(|current| {
+            let mut families = Families::clone(current);
+            let mut memberships = families
+                .members
+                .get(&TypeId::of::<M>())
+                .map(|memberships| memberships.to_vec())
+                .unwrap_or_default();
+            memberships.retain(|(family, _)| *family != TypeId::of::<T>());
+            memberships.push((TypeId::of::<T>(), Arc::clone(&caster)));
+            families
+                .members
+                .insert(TypeId::of::<M>(), Arc::new(memberships));
+            families
+        });
+        tracing::debug!(target: LOG_TARGET, "{:?} was added to the family {:?}", std::any::type_name::<M>(), std::any::type_name::<T>());
+
+        self
+    }
+
+    /// Register the handler of the commands in the family of the trait object `T`.
+    /// It handles the members that do not have a handler of their own and is
+    /// tried before the fallbacks. Replaces the handler already registered for the family
+    ///
+    /// The handler can use `DispatchedCommand::as_dyn::<T>` to reach the command
+    pub async fn register_command_family<T: ?Sized + 'static>(
+        &self,
+        handler: impl CommandHandler + 'static,
+    ) -> &Self {
+        let name = std::any::type_name::<T>();
+        tracing::debug!(target: LOG_TARGET, "registered command family handler {:?} for {:?}", handler.command_handler_name(), name);
+        let manager = Arc::new(CommandHandlerManager::new(handler).await.for_message(name));
+        self.families.rcu(|current| {
+            let mut families = Families::clone(current);
+            families
+                .commands
+                .insert(TypeId::of::<T>(), Arc::clone(&manager));
+            families
+        });
+
+        self
+    }
+
+    /// Removes the handler of the command family.
+    /// Returns false if the family did not have a handler
+    pub async fn unregister_command_family<T: ?Sized + 'static>(&self) -> bool {
+        let previous = self.families.rcu(|current| {
+            let mut families = Families::clone(current);
+            families.commands.remove(&TypeId::of::<T>());
+            families
+        });
+
+        previous.commands.contains_key(&TypeId::of::<T>())
+    }
+
+    /// Register the handler of the queries in the family of the trait object `T`.
+    ///
+    /// See [`Busstop::register_command_family`]
+    pub async fn register_query_family<T: ?Sized + 'static>(
+        &self,
+        handler: impl QueryHandler + 'static,
+    ) -> &Self {
+        let name = std::any::type_name::<T>();
+        tracing::debug!(target: LOG_TARGET, "registered query family handler {:?} for {:?}", handler.query_handler_name(), name);
+        let manager = Arc::new(QueryHandlerManager::new(handler).await.for_message(name));
+        self.families.rcu(|current| {
+            let mut families = Families::clone(current);
+            families
+                .queries
+                .insert(TypeId::of::<T>(), Arc::clone(&manager));
+            families
+        });
+
+        self
+    }
+
+    /// Removes the handler of the query family.
+    /// Returns false if the family did not have a handler
+    pub async fn unregister_query_family<T: ?Sized + 'static>(&self) -> bool {
+        let previous = self.families.rcu(|current| {
+            let mut families = Families::clone(current);
+            families.queries.remove(&TypeId::of::<T>());
+            families
+        });
+
+        previous.queries.contains_key(&TypeId::of::<T>())
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::sync::Mutex;
+
+    use super::*;
+    use crate::{DispatchedCommand, DispatchedQuery};
+
+    trait Auditable: Send + Sync {
+        fn subject(&self) -> String;
+    }
+
+    trait Paginated: Send + Sync {
+        fn page(&self) -> u32;
+    }
+
+    struct CreateUser {
+        name: String,
+    }
+
+    impl Auditable for CreateUser {
+        fn subject(&self) -> String {
+            format!("user {}", self.name)
+        }
+    }
+
+    struct DeleteUser(u32);
+
+    impl Auditable for DeleteUser {
+        fn subject(&self) -> String {
+            format!("user #{}", self.0)
+        }
+    }
+
+    struct ListUsers {
+        page: u32,
+    }
+
+    impl Paginated for ListUsers {
+        fn page(&self) -> u32 {
+            self.page
+        }
+    }
+
+    struct AuditLog(Arc<Mutex<Vec<String>>>);
+
+    #[async_trait::async_trait]
+    impl CommandHandler for AuditLog {
+        async fn handle_command(&self, dispatched: DispatchedCommand) -> DispatchedCommand {
+            let subject = match dispatched.as_dyn::<dyn Auditable>() {
+                Some(command) => command.subject(),
+                None => dispatched.name().to_string(),
+            };
+            self.0.lock().unwrap().push(subject);
+            dispatched
+        }
+    }
+
+    struct FirstPage;
+
+    #[async_trait::async_trait]
+    impl QueryHandler for FirstPage {
+        async fn handle_query(&self, dispatched: DispatchedQuery) -> DispatchedQuery {
+            let page = dispatched.as_dyn::<dyn Paginated>().map(|q| q.page());
+            dispatched.set_value(page == Some(1));
+            dispatched
+        }
+    }
+
+    #[tokio::test]
+    async fn test_command_family() {
+        let audit = Arc::new(Mutex::new(Vec::new()));
+        let fallback = Arc::new(Mutex::new(Vec::new()));
+
+        let bus = Busstop::new();
+        bus.add_to_family::<CreateUser, dyn Auditable>(|c| c)
+            .await
+            .add_to_family::<DeleteUser, dyn Auditable>(|c| c)
+            .await
+            .register_command_family::<dyn Auditable>(AuditLog(audit.clone()))
+            .await
+            .register_command_fallback(AuditLog(fallback.clone()))
+            .await
+            .register_command_fn::<DeleteUser, _>(|_: DeleteUser| async {})
+            .await;
+
+        assert!(
+            bus.dispatch_command(CreateUser {
+                name: "ada".to_string()
+            })
+            .await
+        );
+        assert!(bus.dispatch_command(DeleteUser(7)).await);
+        assert_eq!(*audit.lock().unwrap(), vec!["user ada".to_string()]);
+
+        assert!(bus.unregister_command_family::<dyn Auditable>().await);
+        assert!(!bus.unregister_command_family::<dyn Auditable>().await);
+        assert!(
+            bus.dispatch_command(CreateUser {
+                name: "bob".to_string()
+            })
+            .await
+        );
+        assert_eq!(audit.lock().unwrap().len(), 1);
+        assert_eq!(
+            *fallback.lock().unwrap(),
+            vec![std::any::type_name::<CreateUser>().to_string()]
+        );
+    }
+
+    #[tokio::test]
+    async fn test_query_family() {
+        let bus = Busstop::new();
+        assert!(!bus.dispatch_query(ListUsers { page: 1 }).await.handled());
+
+        bus.add_to_family::<ListUsers, dyn Paginated>(|q| q)
+            .await
+            .register_query_family::<dyn Paginated>(FirstPage)
+            .await;
+
+        let result = bus.dispatch_query(ListUsers { page: 1 }).await;
+        assert!(result.handled());
+        assert_eq!(result.value::<bool>(), Some(&true));
+        assert!(result.as_dyn::<dyn Auditable>().is_none());
+    }
+}
@@ -0,0 +1,339 @@
+use std::{
+    any::{Any, TypeId},
+    sync::Arc,
+};
+
+use crate::{
+    Busstop, DispatchError, DispatchedQuery, Metadata, QueryFn, QueryFnHandler, QueryHandler,
+    query::QueryHandlerManager,
+};
+
+const LOG_TARGET: &str = "bus_stop";
+
+/// The answer of one contributor to a gathered query
+#[derive(Debug)]
+pub struct Answer {
+    handler: String,
+    dispatched: DispatchedQuery,
+}
+
+impl Answer {
+    /// The name of the contributor that answered
+    pub fn handler(&self) -> &str {
+        &self.handler
+    }
+
+    /// The query as returned by the contributor
+    pub fn dispatched(&self) -> &DispatchedQuery {
+        &self.dispatched
+    }
+
+    /// Returns the query as returned by the contributor
+    pub fn into_dispatched(self) -> DispatchedQuery {
+        self.dispatched
+    }
+
+    /// Returns true if the contributor did not fail
+    pub fn succeeded(&self) -> bool {
+        !self.dispatched.is_failed()
+    }
+
+    /// Returns the value set by the contributor
+    pub fn value<T: 'static>(&self) -> Option<&T> {
+        self.dispatched.value()
+    }
+}
+
+/// The answers of all the contributors of a query, in the order
+/// the contributors were registered
+#[derive(Debug)]
+pub struct GatheredQuery {
+    name: &'static str,
+    answers: Vec<Answer>,
+    error: Option<DispatchError>,
+}
+
+impl GatheredQuery {
+    /// The type name of the query
+    pub fn name(&self) -> &'static str {
+        self.name
+    }
+
+    /// Returns the reason the query was not scattered, for example
+    /// when the caller was not authorized
+    pub fn error(&self) -> Option<&DispatchError> {
+        self.error.as_ref()
+    }
+
+    /// The answers of the contributors
+    pub fn answers(&self) -> &[Answer] {
+        &self.answers
+    }
+
+    /// Returns the answers of the contributors
+    pub fn into_answers(self) -> Vec<Answer> {
+        self.answers
+    }
+
+    /// The number of contributors that answered
+    pub fn len(&self) -> usize {
+        self.answers.len()
+    }
+
+    /// Returns true if no contributor answered
+    pub fn is_empty(&self) -> bool {
+        self.answers.is_empty()
+    }
+
+    /// The contributors that failed along with the reason
+    pub fn errors(&self) -> impl Iterator<Item = (&str, &DispatchError)> {
+        self.answers
+            .iter()
+            .filter_map(|answer| Some((answer.handler(), answer.dispatched.error()?)))
+    }
+
+    /// Returns the value of the first contributor that succeeded with a value of type `T`
+    pub fn first_success<T: 'static>(self) -> Option<T> {
+        self.values().next()
+    }
+
+    /// Returns the values of type `T` of every contributor that succeeded
+    pub fn collect_all<T: 'static>(self) -> Vec<T> {
+        self.values().collect()
+    }
+
+    /// Folds the values of type `T` of every contributor that succeeded into one
+    ///
+    /// ```rust,ignore
+    /// let menu = bus
+    ///     .gather_query(MenuItems)
+    ///     .await
+    ///     .merge(Vec::new(), |mut menu, items: Vec<MenuItem>| {
+    ///         menu.extend(items);
+    ///         menu
+    ///     });
+    /// ```
+    pub fn merge<T: 'static, R>(self, init: R, f: impl FnMut(R, T) -> R) -> R {
+        self.values().fold(init, f)
+    }
+
+    fn values<T: 'static>(self) -> impl Iterator<Item = T> {
+        self.answers
+            .into_iter()
+            .filter(Answer::succeeded)
+            .filter_map(|mut answer| answer.dispatched.take_value::<T>().map(|value| *value))
+    }
+}
+
+impl Busstop {
+    /// Adds a contributor to the query. Every contributor answers a
+    /// query dispatched with `gather_query`. Contributors are separate
+    /// from the handler registered with `register_query`
+    pub async fn register_query_contributor<Q: 'static>(
+        &self,
+        handler: impl QueryHandler + 'static,
+    ) -> &Self {
+        let name = std::any::type_name::<Q>();
+        tracing::debug!(target: LOG_TARGET, "registered query contributor {:?} for {:?}", handler.query_handler_name(), name);
+        let manager = QueryHandlerManager::new(handler).await;
+
+        self.contributors
+            .write()
+            .await
+            .entry(TypeId::of::<Q>())
+            .or_default()
+            .push(Arc::new(manager));
+        self.refreeze().await;
+
+        self
+    }
+
+    /// Register an async function or closure as a contributor of a query
+    pub async fn register_query_contributor_fn<Q, F>(&self, handler: F) -> &Self
+    where
+        Q: Send + Sync + 'static,
+        F: QueryFn<Q>,
+    {
+        self.register_query_contributor::<Q>(QueryFnHandler::new(handler))
+            .await
+    }
+
+    /// Removes every contributor of the query.
+    /// Returns the number of contributors that were removed
+    pub async fn unregister_query_contributors<Q: 'static>(&self) -> usize {
+        let removed = self
+            .contributors
+            .write()
+            .await
+            .remove(&TypeId::of::<Q>())
+            .map_or(0, |contributors| contributors.len());
+        if removed > 0 {
+            self.refreeze().await;
+        }
+
+        removed
+    }
+
+    /// Sends a copy of the query to every contributor and waits for all of
+    /// them to answer. The contributors run concurrently
+    pub async fn gather_query<Q: Clone + Send + Sync + 'static>(&self, query: Q) -> GatheredQuery {
+        self.gather_query_with_metadata(query, Metadata::new())
+            .await
+    }
+
+    /// Same as `gather_query` but passes the metadata along with the query
+    pub async fn gather_query_with_metadata<Q: Clone + Send + Sync + 'static>(
+        &self,
+        query: Q,
+        metadata: Metadata,
+    ) -> GatheredQuery {
+        let name = std::any::type_name::<Q>();
+        tracing::debug!(target: LOG_TARGET, "gathering query: {:?}", name);
+
+        let mut gathered = GatheredQuery {
+            name,
+            answers: Vec::new(),
+            error: None,
+        };
+
+        let type_id = TypeId::of::<Q>();
+        let contributors = match self.frozen_contributors(type_id) {
+            Some(contributors) => contributors,
+            None => self
+                .contributors
+                .read()
+                .await
+                .get(&type_id)
+                .cloned()
+                .unwrap_or_default(),
+        };
+
+        if !contributors.is_empty()
+            && let Err(error) = self.authorization.load().check_query(
+                name,
+                Some(&query as &(dyn Any + Send + Sync)),
+                &metadata,
+            )
+        {
+            gathered.error = Some(error);
+            return gathered;
+        }
+
+        gathered.answers = futures::future::join_all(contributors.iter().map(|contributor| {
+            let dispatched =
+                DispatchedQuery::new(Box::new(query.clone()), name).with_metadata(metadata.clone());
+            async move {
+                Answer {
+                    handler: contributor.name().clone(),
+                    dispatched: contributor.handle(dispatched).await,
+                }
+            }
+        }))
+        .await;
+        tracing::debug!(target: LOG_TARGET, "query: {:?} was answered by {} contributors", name, gathered.answers.len());
+
+        gathered
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::time::Duration;
+
+    use tokio::sync::Barrier;
+
+    use super::*;
+
+    #[derive(Clone)]
+    struct MenuItems {
+        section: &'static str,
+    }
+
+    #[tokio::test]
+    async fn test_contributors_answer_concurrently() {
+        // Every contributor waits for the others, the gathering only
+        // completes when they all run at the same time
+        let barrier = Arc::new(Barrier::new(3));
+        let (users, other, billing) = (barrier.clone(), barrier.clone(), barrier);
+
+        let bus = Busstop::new();
+        bus.register_query_contributor_fn::<MenuItems, _>(move |q: &MenuItems| {
+            let (section, barrier) = (q.section, users.clone());
+            async move {
+                barrier.wait().await;
+                vec![format!("{section}/users")]
+            }
+        })
+        .await
+        .register_query_contributor_fn::<MenuItems, _>(move |_: &MenuItems| {
+            let barrier = other.clone();
+            async move {
+                barrier.wait().await;
+                "not a list"
+            }
+        })
+        .await
+        .register_query_contributor_fn::<MenuItems, _>(move |q: &MenuItems| {
+            let (section, barrier) = (q.section, billing.clone());
+            async move {
+                barrier.wait().await;
+                vec![format!("{section}/billing")]
+            }
+        })
+        .await;
+
+        let gathered = tokio::time::timeout(
+            Duration::from_secs(5),
+            bus.gather_query(MenuItems { section: "admin" }),
+        )
+        .await
+        .expect("the contributors did not run concurrently");
+        assert_eq!(gathered.len(), 3);
+        assert!(
+            gathered
+                .answers()
+                .iter()
+                .all(|a| a.handler().contains("gather"))
+        );
+
+        let menu = gathered.merge(Vec::new(), |mut menu, items: Vec<String>| {
+            menu.extend(items);
+            menu
+        });
+        assert_eq!(menu, vec!["admin/users", "admin/billing"]);
+
+        let first = bus
+            .gather_query(MenuItems { section: "site" })
+            .await
+            .first_success::<Vec<String>>();
+        assert_eq!(first, Some(vec!["site/users".to_string()]));
+
+        let all = bus
+            .gather_query(MenuItems { section: "site" })
+            .await
+            .collect_all::<&str>();
+        assert_eq!(all, vec!["not a list"]);
+    }
+
+    #[tokio::test]
+    async fn test_failed_contributor_is_reported() {
+        let bus = Busstop::new();
+        assert!(bus.gather_query(MenuItems { section: "" }).await.is_empty());
+
+        bus.register_query_contributor_fn::<MenuItems, _>(|_: &MenuItems| async {
+            panic!("menu is on fire");
+            #[allow(unreachable_code)]
+            1
+        })
+        .await
+        .register_query_contributor_fn::<MenuItems, _>(|_: &MenuItems| async { 2 })
+        .await;
+
+        let gathered = bus.gather_query(MenuItems { section: "" }).await;
+        assert_eq!(gathered.errors().count(), 1);
+        assert_eq!(gathered.first_success::<i32>(), Some(2));
+
+        assert_eq!(bus.unregister_query_contributors::<MenuItems>().await, 2);
+        assert!(bus.gather_query(MenuItems { section: "" }).await.is_empty());
+    }
+}
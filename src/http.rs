@@ -0,0 +1,421 @@
+use std::{collections::HashSet, sync::Arc};
+
+use axum::{
+    Json, Router,
+    body::Bytes,
+    extract::{Extension, Path, State},
+    http::{HeaderMap, StatusCode, header},
+    response::{IntoResponse, Response},
+    routing::post,
+};
+use serde::Serialize;
+
+use crate::{
+    Busstop, DispatchError, DynamicDispatchError, JsonCodec, Metadata, PRINCIPAL_METADATA_KEY,
+    SerializableMessage, ValidationErrors, authorization::untrusted,
+};
+
+const LOG_TARGET: &str = "bus_stop::http";
+
+/// Request headers starting with this prefix are passed along as metadata.
+/// The prefix is removed from the key
+///
+/// The `x-busstop-principal` header is dropped, see [`Principal`]
+pub const METADATA_HEADER_PREFIX: &str = "x-busstop-";
+
+/// The principal the request was authenticated as
+///
+/// Authentication layers insert it into the request extensions. The
+/// routes of `Busstop::http_router` copy it into the metadata under
+/// `PRINCIPAL_METADATA_KEY`, it is the only way to set the principal
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Principal(pub String);
+
+/// The commands and queries exposed over HTTP
+#[derive(Default)]
+pub(crate) struct HttpRoutes {
+    commands: HashSet<&'static str>,
+    queries: HashSet<&'static str>,
+}
+
+impl Busstop {
+    /// Exposes the command at `POST /commands/{name}` on the router
+    /// returned by `http_router`
+    ///
+    /// The command is registered as a dynamic command
+    pub async fn expose_http_command<C: SerializableMessage>(&self) -> &Self {
+        self.register_dynamic_command::<C>().await;
+        self.http.write().await.commands.insert(C::message_name());
+
+        self
+    }
+
+    /// Exposes the query at `POST /queries/{name}` on the router
+    /// returned by `http_router`. `V` is the type of the value the query's handler sets
+    ///
+    /// The query is registered as a dynamic query
+    pub async fn expose_http_query<Q, V>(&self) -> &Self
+    where
+        Q: SerializableMessage,
+        V: Serialize + Send + Sync + 'static,
+    {
+        self.register_dynamic_query::<Q, V>().await;
+        self.http.write().await.queries.insert(Q::message_name());
+
+        self
+    }
+
+    /// Returns a router that dispatches JSON requests to the exposed
+    /// commands and queries
+    ///
+    /// | Outcome | Status |
+    /// | ------- | ------ |
+    /// | command handled | 204 |
+    /// | query handled | 200 with the value as JSON, 204 when no value was set |
+    /// | body could not be read | 400 |
+    /// | unknown message or no handler | 404 |
+    /// | `DispatchError::Invalid` | 422 |
+    /// | `DispatchError::Validation` | 422, the fields are listed under `fields` |
+    /// | `DispatchError::Forbidden` | 403 |
+    /// | `DispatchError::RateLimited` | 429 |
+    /// | `DispatchError::Failed` or `DispatchError::Panicked` | 500 |
+    pub fn http_router(self: Arc<Self>) -> Router {
+        Router::new()
+            .route("/commands/{name}", post(command_route))
+            .route("/queries/{name}", post(query_route))
+            .with_state(self)
+    }
+}
+
+async fn command_route(
+    State(bus): State<Arc<Busstop>>,
+    Path(name): Path<String>,
+    principal: Option<Extension<Principal>>,
+    headers: HeaderMap,
+    body: Bytes,
+) -> Response {
+    if !bus.http.read().await.commands.contains(name.as_str()) {
+        return not_found(&name);
+    }
+
+    let dispatched = match bus.decode_command(&name, &body, &JsonCodec).await {
+        Ok(dispatched) => dispatched.with_metadata(metadata(&headers, principal)),
+        Err(e) => return decode_error(&name, e),
+    };
+
+    let result = bus.route_command(dispatched).await;
+    if let Some(error) = result.error() {
+        error_response(error)
+    } else if !result.handled() {
+        not_found(&name)
+    } else {
+        StatusCode::NO_CONTENT.into_response()
+    }
+}
+
+async fn query_route(
+    State(bus): State<Arc<Busstop>>,
+    Path(name): Path<String>,
+    principal: Option<Extension<Principal>>,
+    headers: HeaderMap,
+    body: Bytes,
+) -> Response {
+    if !bus.http.read().await.queries.contains(name.as_str()) {
+        return not_found(&name);
+    }
+
+    let (dispatched, encode_value) = match bus.decode_query(&name, &body, &JsonCodec).await {
+        Ok((dispatched, encode_value)) => (
+            dispatched.with_metadata(metadata(&headers, principal)),
+            encode_value,
+        ),
+        Err(e) => return decode_error(&name, e),
+    };
+
+    let result = bus.route_query(dispatched).await;
+    if let Some(error) = result.error() {
+        return error_response(error);
+    } else if !result.handled() {
+        return not_found(&name);
+    }
+
+    match encode_value(&JsonCodec, &result) {
+        Ok(Some(value)) => ([(header::CONTENT_TYPE, "application/json")], value).into_response(),
+        Ok(None) => StatusCode::NO_CONTENT.into_response(),
+        Err(e) => {
+            tracing::error!(target: LOG_TARGET, "could not serialize the value of {}: {}", name, e);
+            error_response(&DispatchError::Failed(e.to_string()))
+        }
+    }
+}
+
+fn metadata(headers: &HeaderMap, principal: Option<Extension<Principal>>) -> Metadata {
+    let mut metadata = untrusted(
+        headers
+            .iter()
+            .filter_map(|(key, value)| {
+                let key = key.as_str().strip_prefix(METADATA_HEADER_PREFIX)?;
+                Some((key, value.to_str().ok()?))
+            })
+            .collect(),
+    );
+    if let Some(Extension(Principal(principal))) = principal {
+        metadata.insert(PRINCIPAL_METADATA_KEY, principal);
+    }
+
+    metadata
+}
+
+#[derive(Serialize)]
+struct ErrorBody {
+    error: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    fields: Option<ValidationErrors>,
+}
+
+fn error_body(status: StatusCode, error: impl ToString) -> Response {
+    (
+        status,
+        Json(ErrorBody {
+            error: error.to_string(),
+            fields: None,
+        }),
+    )
+        .into_response()
+}
+
+fn not_found(name: &str) -> Response {
+    error_body(StatusCode::NOT_FOUND, format!("no handler for {}", name))
+}
+
+fn decode_error(name: &str, error: DynamicDispatchError) -> Response {
+    match error {
+        DynamicDispatchError::UnknownMessage(_) => not_found(name),
+        DynamicDispatchError::Codec(e) => error_body(StatusCode::BAD_REQUEST, e),
+    }
+}
+
+fn error_response(error: &DispatchError) -> Response {
+    let status = match error {
+        DispatchError::Validation(errors) => {
+            return (
+                StatusCode::UNPROCESSABLE_ENTITY,
+                Json(ErrorBody {
+                    error: error.to_string(),
+                    fields: Some(errors.clone()),
+                }),
+            )
+                .into_response();
+        }
+        DispatchError::Invalid(_) => StatusCode::UNPROCESSABLE_ENTITY,
+        DispatchError::Forbidden(_) => StatusCode::FORBIDDEN,
+        DispatchError::RateLimited => StatusCode::TOO_MANY_REQUESTS,
+        DispatchError::Failed(_) | DispatchError::Panicked(_) => StatusCode::INTERNAL_SERVER_ERROR,
+    };
+
+    error_body(status, error)
+}
+
+#[cfg(test)]
+mod test {
+    use axum::{body::Body, http::Request};
+    use serde::Deserialize;
+    use tower::ServiceExt;
+
+    use super::*;
+
+    #[derive(Serialize, Deserialize)]
+    struct RegisterUser {
+        email: String,
+    }
+    impl SerializableMessage for RegisterUser {
+        fn message_name() -> &'static str {
+            "register_user"
+        }
+    }
+
+    #[derive(Serialize, Deserialize)]
+    struct UserCount;
+    impl SerializableMessage for UserCount {
+        fn message_name() -> &'static str {
+            "user_count"
+        }
+    }
+
+    #[derive(Serialize, Deserialize)]
+    struct DeleteUser;
+    impl SerializableMessage for DeleteUser {
+        fn message_name() -> &'static str {
+            "delete_user"
+        }
+    }
+
+    #[derive(Serialize, Deserialize)]
+    struct RenameUser {
+        name: String,
+    }
+    impl SerializableMessage for RenameUser {
+        fn message_name() -> &'static str {
+            "rename_user"
+        }
+    }
+    impl crate::Validate for RenameUser {
+        fn validate(&self) -> Result<(), ValidationErrors> {
+            if self.name.is_empty() {
+                return Err(ValidationErrors::from_iter([("name", "is required")]));
+            }
+            Ok(())
+        }
+    }
+
+    async fn bus() -> Arc<Busstop> {
+        let bus = Arc::new(Busstop::new());
+        bus.register_command_middleware::<RegisterUser, _>(|mut c, n| {
+            Box::pin(async move {
+                let email = c.the_command::<RegisterUser>().map(|c| c.email.clone());
+                match email.as_deref() {
+                    Some("") => c.fail(DispatchError::Invalid("email is required".to_string())),
+                    Some("spam@spam.com") => c.fail(DispatchError::RateLimited),
+                    Some("boom@boom.com") => c.fail(DispatchError::Failed("boom".to_string())),
+                    _ => return n.call(c).await,
+                }
+                c
+            })
+        })
+        .await
+        .register_command_fn::<RegisterUser, _>(|_: RegisterUser| async {})
+        .await
+        .register_query_fn::<UserCount, _>(|_: &UserCount| async { 5_u32 })
+        .await
+        .register_query_middleware::<UserCount, _>(|q, n| {
+            Box::pin(async move {
+                assert_eq!(q.metadata().get("tenant"), Some("acme"));
+                n.call(q).await
+            })
+        })
+        .await
+        .expose_http_command::<RegisterUser>()
+        .await
+        .expose_http_command::<DeleteUser>()
+        .await
+        .expose_http_query::<UserCount, u32>()
+        .await;
+
+        bus
+    }
+
+    async fn post(bus: &Arc<Busstop>, uri: &str, body: &str) -> (StatusCode, Bytes) {
+        send(
+            bus.clone().http_router(),
+            Request::post(uri)
+                .header("x-busstop-tenant", "acme")
+                .body(Body::from(body.to_string()))
+                .unwrap(),
+        )
+        .await
+    }
+
+    async fn send(router: Router, request: Request<Body>) -> (StatusCode, Bytes) {
+        let response = router.oneshot(request).await.unwrap();
+        let status = response.status();
+        let body = axum::body::to_bytes(response.into_body(), usize::MAX)
+            .await
+            .unwrap();
+
+        (status, body)
+    }
+
+    #[tokio::test]
+    async fn test_command_routes() {
+        let bus = bus().await;
+        let cases = [
+            (
+                "/commands/register_user",
+                r#"{"email":"a@b.com"}"#,
+                StatusCode::NO_CONTENT,
+            ),
+            (
+                "/commands/register_user",
+                r#"{"email":""}"#,
+                StatusCode::UNPROCESSABLE_ENTITY,
+            ),
+            (
+                "/commands/register_user",
+                r#"{"email":"spam@spam.com"}"#,
+                StatusCode::TOO_MANY_REQUESTS,
+            ),
+            (
+                "/commands/register_user",
+                r#"{"email":"boom@boom.com"}"#,
+                StatusCode::INTERNAL_SERVER_ERROR,
+            ),
+            (
+                "/commands/register_user",
+                r#"{"mail":"a@b.com"}"#,
+                StatusCode::BAD_REQUEST,
+            ),
+            ("/commands/delete_user", "null", StatusCode::NOT_FOUND),
+            ("/commands/unknown", "{}", StatusCode::NOT_FOUND),
+        ];
+
+        for (uri, body, expected) in cases {
+            assert_eq!(post(&bus, uri, body).await.0, expected, "{} {}", uri, body);
+        }
+    }
+
+    #[tokio::test]
+    async fn test_validation_errors() {
+        let bus = Arc::new(Busstop::new());
+        bus.register_command_validation::<RenameUser>()
+            .await
+            .register_command_fn::<RenameUser, _>(|_: RenameUser| async {})
+            .await
+            .expose_http_command::<RenameUser>()
+            .await;
+
+        let (status, body) = post(&bus, "/commands/rename_user", r#"{"name":""}"#).await;
+        assert_eq!(status, StatusCode::UNPROCESSABLE_ENTITY);
+        assert_eq!(
+            serde_json::from_slice::<serde_json::Value>(&body).unwrap()["fields"],
+            serde_json::json!([{ "field": "name", "message": "is required" }])
+        );
+    }
+
+    #[tokio::test]
+    async fn test_query_route() {
+        let bus = bus().await;
+        let (status, body) = post(&bus, "/queries/user_count", "null").await;
+
+        assert_eq!(status, StatusCode::OK);
+        assert_eq!(&body[..], b"5");
+    }
+
+    #[tokio::test]
+    async fn test_principal_header_is_not_trusted() {
+        let bus = Arc::new(Busstop::new());
+        bus.register_command_fn::<DeleteUser, _>(|_: DeleteUser| async {})
+            .await
+            .register_command_policy::<DeleteUser>(|principal: Option<&str>, _: &DeleteUser| {
+                principal == Some("admin")
+            })
+            .await
+            .expose_http_command::<DeleteUser>()
+            .await;
+        let request = || {
+            Request::post("/commands/delete_user")
+                .header("x-busstop-principal", "admin")
+                .body(Body::from("null"))
+                .unwrap()
+        };
+
+        let (status, _) = send(bus.clone().http_router(), request()).await;
+        assert_eq!(status, StatusCode::FORBIDDEN);
+
+        let router = bus
+            .clone()
+            .http_router()
+            .layer(Extension(Principal("admin".to_string())));
+        let (status, _) = send(router, request()).await;
+        assert_eq!(status, StatusCode::NO_CONTENT);
+    }
+}
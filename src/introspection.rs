@@ -0,0 +1,185 @@
+use std::fmt::Display;
+
+use crate::Busstop;
+
+/// What the bus knows about a command or query type
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct MessageInfo {
+    /// The type name of the message
+    pub message: &'static str,
+    /// The names of the handlers, in the order they are called
+    pub handlers: Vec<&'static str>,
+    /// The enum variants that are deliberately not routed to a handler
+    pub unrouted_variants: Vec<&'static str>,
+}
+
+impl Display for MessageInfo {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{} -> {}", self.message, self.handlers.join(", "))?;
+        if !self.unrouted_variants.is_empty() {
+            write!(f, " (unrouted: {})", self.unrouted_variants.join(", "))?;
+        }
+        Ok(())
+    }
+}
+
+/// The commands and queries registered on a bus, sorted by type name
+#[derive(Debug, Clone, PartialEq, Eq, Default)]
+pub struct Introspection {
+    /// The commands with a handler
+    pub commands: Vec<MessageInfo>,
+    /// The queries with a handler
+    pub queries: Vec<MessageInfo>,
+}
+
+impl Introspection {
+    /// Returns the command with this type name
+    pub fn command(&self, message: &str) -> Option<&MessageInfo> {
+        self.commands.iter().find(|info| info.message == message)
+    }
+
+    /// Returns the query with this type name
+    pub fn query(&self, message: &str) -> Option<&MessageInfo> {
+        self.queries.iter().find(|info| info.message == message)
+    }
+}
+
+impl Display for Introspection {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        for info in &self.commands {
+            writeln!(f, "command {}", info)?;
+        }
+        for info in &self.queries {
+            writeln!(f, "query {}", info)?;
+        }
+        Ok(())
+    }
+}
+
+impl Busstop {
+    /// Lists the commands and queries that have a handler
+    ///
+    /// A message whose handler is overridden is reported with the
+    /// override, which has no unrouted variants
+    pub async fn introspect(&self) -> Introspection {
+        let unrouted = self.unrouted_variants.read().await;
+        let overrides = self.overrides.load();
+
+        let mut commands = self
+            .commands
+            .read()
+            .await
+            .iter()
+            .filter(|(type_id, _)| overrides.command(**type_id).is_none())
+            .map(|(type_id, manager)| MessageInfo {
+                message: manager.message(),
+                handlers: manager.handler_names(),
+                unrouted_variants: unrouted.commands.get(type_id).cloned().unwrap_or_default(),
+            })
+            .chain(overrides.commands().map(|(_, manager)| MessageInfo {
+                message: manager.message(),
+                handlers: manager.handler_names(),
+                unrouted_variants: Vec::new(),
+            }))
+            .collect::<Vec<_>>();
+        commands.sort_by_key(|info| info.message);
+
+        let mut queries = self
+            .queries
+            .read()
+            .await
+            .iter()
+            .filter(|(type_id, _)| overrides.query(**type_id).is_none())
+            .map(|(type_id, manager)| MessageInfo {
+                message: manager.message(),
+                handlers: manager.handler_names(),
+                unrouted_variants: unrouted.queries.get(type_id).cloned().unwrap_or_default(),
+            })
+            .chain(overrides.queries().map(|(_, manager)| MessageInfo {
+                message: manager.message(),
+                handlers: manager.handler_names(),
+                unrouted_variants: Vec::new(),
+            }))
+            .collect::<Vec<_>>();
+        queries.sort_by_key(|info| info.message);
+
+        Introspection { commands, queries }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::{CommandFnHandler, command_variants};
+
+    #[allow(dead_code)]
+    enum Door {
+        Open,
+        Close,
+        Lock(u32),
+    }
+
+    struct Ring;
+
+    #[tokio::test]
+    async fn test_introspection_reports_unrouted_variants() {
+        let bus = Busstop::new();
+        bus.register_command_variants(command_variants!(Door {
+            Open => CommandFnHandler::new(|_: Door| async {}),
+            Close => CommandFnHandler::new(|_: Door| async {}),
+        } unrouted { Lock }))
+            .await
+            .register_query_fn::<Ring, _>(|_: &Ring| async { true })
+            .await;
+
+        let introspection = bus.introspect().await;
+        let door = introspection
+            .command(std::any::type_name::<Door>())
+            .unwrap();
+        assert_eq!(door.unrouted_variants, vec!["Lock"]);
+        assert_eq!(door.handlers.len(), 1);
+
+        let ring = introspection.query(std::any::type_name::<Ring>()).unwrap();
+        assert!(ring.unrouted_variants.is_empty());
+        assert!(introspection.to_string().contains("(unrouted: Lock)"));
+
+        bus.unregister_command::<Door>().await;
+        assert!(bus.introspect().await.commands.is_empty());
+    }
+
+    #[tokio::test]
+    async fn test_unrouted_variants_follow_the_handler_in_use() {
+        let bus = Busstop::new();
+        let unrouted = |introspection: Introspection| {
+            introspection
+                .command(std::any::type_name::<Door>())
+                .map(|door| door.unrouted_variants.clone())
+        };
+
+        bus.register_command_fn::<Door, _>(|_: Door| async {}).await;
+        let duplicate = futures::FutureExt::catch_unwind(std::panic::AssertUnwindSafe(
+            bus.register_command_variants(command_variants!(Door {
+                Open => CommandFnHandler::new(|_: Door| async {}),
+            } unrouted { Close, Lock })),
+        ))
+        .await;
+        assert!(duplicate.is_err());
+        assert_eq!(unrouted(bus.introspect().await), Some(Vec::new()));
+
+        bus.unregister_command::<Door>().await;
+        bus.register_command_variants(command_variants!(Door {
+            Open => CommandFnHandler::new(|_: Door| async {}),
+        } unrouted { Close, Lock }))
+            .await;
+        let guard = bus
+            .override_command::<Door>(CommandFnHandler::new(|_: Door| async {}))
+            .await;
+        assert_eq!(unrouted(bus.introspect().await), Some(Vec::new()));
+
+        guard.restore();
+        assert_eq!(
+            unrouted(bus.introspect().await),
+            Some(vec!["Close", "Lock"])
+        );
+    }
+}
@@ -0,0 +1,432 @@
+//! JSON-RPC 2.0 adapter
+//!
+//! See [`JsonRpcServer`]
+use std::{io, sync::Arc};
+
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use tokio::io::{AsyncBufReadExt, AsyncRead, AsyncWrite, AsyncWriteExt, BufReader};
+
+use crate::{Busstop, DispatchError, DynamicDispatchError, JsonCodec};
+
+const LOG_TARGET: &str = "bus_stop::jsonrpc";
+
+/// Invalid JSON was received
+pub const PARSE_ERROR: i64 = -32700;
+/// The JSON sent is not a valid request object
+pub const INVALID_REQUEST: i64 = -32600;
+/// No command or query is registered with the method name, or it has no handler
+pub const METHOD_NOT_FOUND: i64 = -32601;
+/// The params could not be deserialized into the command or query
+pub const INVALID_PARAMS: i64 = -32602;
+/// The handler or a middleware failed or panicked
+pub const INTERNAL_ERROR: i64 = -32603;
+/// `DispatchError::Invalid` and `DispatchError::Validation`. For the
+/// latter, the fields are listed in `data`
+pub const VALIDATION_ERROR: i64 = -32001;
+/// `DispatchError::RateLimited`
+pub const RATE_LIMITED: i64 = -32002;
+/// `DispatchError::Forbidden`
+pub const FORBIDDEN: i64 = -32003;
+
+/// A JSON-RPC 2.0 error object
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct JsonRpcError {
+    /// The error code
+    pub code: i64,
+    /// A short description of the error
+    pub message: String,
+    /// Additional information about the error
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub data: Option<Value>,
+}
+
+impl JsonRpcError {
+    fn new(code: i64, message: impl ToString) -> Self {
+        Self {
+            code,
+            message: message.to_string(),
+            data: None,
+        }
+    }
+}
+
+impl From<&DispatchError> for JsonRpcError {
+    fn from(error: &DispatchError) -> Self {
+        let code = match error {
+            DispatchError::Validation(errors) => {
+                let mut rpc_error = Self::new(VALIDATION_ERROR, error);
+                rpc_error.data = serde_json::to_value(errors).ok();
+                return rpc_error;
+            }
+            DispatchError::Invalid(_) => VALIDATION_ERROR,
+            DispatchError::Forbidden(_) => FORBIDDEN,
+            DispatchError::RateLimited => RATE_LIMITED,
+            DispatchError::Failed(_) | DispatchError::Panicked(_) => INTERNAL_ERROR,
+        };
+
+        Self::new(code, error)
+    }
+}
+
+#[derive(Serialize)]
+struct Response {
+    jsonrpc: &'static str,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    result: Option<Value>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    error: Option<JsonRpcError>,
+    id: Value,
+}
+
+impl Response {
+    fn result(id: Value, result: Value) -> Self {
+        Self {
+            jsonrpc: "2.0",
+            result: Some(result),
+            error: None,
+            id,
+        }
+    }
+
+    fn error(id: Value, error: JsonRpcError) -> Self {
+        Self {
+            jsonrpc: "2.0",
+            result: None,
+            error: Some(error),
+            id,
+        }
+    }
+}
+
+/// Maps JSON-RPC 2.0 method names to the commands and queries
+/// registered for dynamic dispatch
+///
+/// Queries are looked up first, then commands. The params are deserialized
+/// into the message. A command replies with `null` and a query replies with
+/// its value. Notifications are dispatched as commands and get no reply
+pub struct JsonRpcServer {
+    bus: Arc<Busstop>,
+}
+
+impl JsonRpcServer {
+    /// Create a new instance that dispatches on `bus`
+    pub fn new(bus: Arc<Busstop>) -> Self {
+        Self { bus }
+    }
+
+    /// Reads newline delimited requests from `reader` and writes
+    /// the responses to `writer`, one per line
+    ///
+    /// Completes when the reader is closed
+    pub async fn serve<R, W>(&self, reader: R, mut writer: W) -> io::Result<()>
+    where
+        R: AsyncRead + Unpin,
+        W: AsyncWrite + Unpin,
+    {
+        let mut lines = BufReader::new(reader).lines();
+
+        while let Some(line) = lines.next_line().await? {
+            if line.trim().is_empty() {
+                continue;
+            }
+
+            if let Some(mut response) = self.handle(&line).await {
+                response.push('\n');
+                writer.write_all(response.as_bytes()).await?;
+                writer.flush().await?;
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Serves requests read from stdin and writes the responses to stdout
+    pub async fn serve_stdio(&self) -> io::Result<()> {
+        self.serve(tokio::io::stdin(), tokio::io::stdout()).await
+    }
+
+    /// Handles a single request or a batch of requests.
+    /// Returns `None` when there is nothing to reply with
+    pub async fn handle(&self, request: &str) -> Option<String> {
+        let response = match serde_json::from_str::<Value>(request) {
+            Ok(Value::Array(batch)) if batch.is_empty() => {
+                Some(serde_json::to_value(Response::error(
+                    Value::Null,
+                    JsonRpcError::new(INVALID_REQUEST, "empty batch"),
+                )))
+            }
+            Ok(Value::Array(batch)) => {
+                let responses = futures::future::join_all(batch.into_iter().map(|r| self.call(r)))
+                    .await
+                    .into_iter()
+                    .flatten()
+                    .collect::<Vec<_>>();
+
+                (!responses.is_empty()).then(|| serde_json::to_value(responses))
+            }
+            Ok(request) => self.call(request).await.map(serde_json::to_value),
+            Err(e) => Some(serde_json::to_value(Response::error(
+                Value::Null,
+                JsonRpcError::new(PARSE_ERROR, e),
+            ))),
+        };
+
+        match response? {
+            Ok(response) => Some(response.to_string()),
+            Err(e) => {
+                tracing::error!(target: LOG_TARGET, "could not serialize response: {}", e);
+                None
+            }
+        }
+    }
+
+    async fn call(&self, request: Value) -> Option<Response> {
+        let Value::Object(mut request) = request else {
+            return Some(Response::error(
+                Value::Null,
+                JsonRpcError::new(INVALID_REQUEST, "request must be an object"),
+            ));
+        };
+
+        let id = request.remove("id");
+        let method = match request.remove("method") {
+            Some(Value::String(method)) if request.get("jsonrpc") == Some(&Value::from("2.0")) => {
+                method
+            }
+            _ => {
+                return Some(Response::error(
+                    id.unwrap_or_default(),
+                    JsonRpcError::new(INVALID_REQUEST, "invalid request"),
+                ));
+            }
+        };
+        let params =
+            serde_json::to_vec(&request.remove("params").unwrap_or_default()).unwrap_or_default();
+
+        let Some(id) = id else {
+            // Notifications are commands and never get a reply
+            if let Err(e) = self
+                .bus
+                .dispatch_command_dynamic(&method, &params, &JsonCodec)
+                .await
+            {
+                tracing::debug!(target: LOG_TARGET, "notification {} was not dispatched: {}", method, e);
+            }
+            return None;
+        };
+
+        let result = if self.bus.has_dynamic_query(&method).await {
+            self.dispatch_query(&method, &params).await
+        } else {
+            self.dispatch_command(&method, &params).await
+        };
+
+        Some(match result {
+            Ok(value) => Response::result(id, value),
+            Err(error) => Response::error(id, error),
+        })
+    }
+
+    async fn dispatch_command(&self, method: &str, params: &[u8]) -> Result<Value, JsonRpcError> {
+        let dispatched = self
+            .bus
+            .decode_command(method, params, &JsonCodec)
+            .await
+            .map_err(|e| decode_error(method, e))?;
+
+        let result = self.bus.route_command(dispatched).await;
+        if let Some(error) = result.error() {
+            Err(error.into())
+        } else if !result.handled() {
+            Err(method_not_found(method))
+        } else {
+            Ok(Value::Null)
+        }
+    }
+
+    async fn dispatch_query(&self, method: &str, params: &[u8]) -> Result<Value, JsonRpcError> {
+        let (dispatched, encode_value) = self
+            .bus
+            .decode_query(method, params, &JsonCodec)
+            .await
+            .map_err(|e| decode_error(method, e))?;
+
+        let result = self.bus.route_query(dispatched).await;
+        if let Some(error) = result.error() {
+            return Err(error.into());
+        } else if !result.handled() {
+            return Err(method_not_found(method));
+        }
+
+        match encode_value(&JsonCodec, &result) {
+            Ok(Some(value)) => {
+                serde_json::from_slice(&value).map_err(|e| JsonRpcError::new(INTERNAL_ERROR, e))
+            }
+            Ok(None) => Ok(Value::Null),
+            Err(e) => Err(JsonRpcError::new(INTERNAL_ERROR, e)),
+        }
+    }
+}
+
+fn method_not_found(method: &str) -> JsonRpcError {
+    JsonRpcError::new(METHOD_NOT_FOUND, format!("method not found: {}", method))
+}
+
+fn decode_error(method: &str, error: DynamicDispatchError) -> JsonRpcError {
+    match error {
+        DynamicDispatchError::UnknownMessage(_) => method_not_found(method),
+        DynamicDispatchError::Codec(e) => JsonRpcError::new(INVALID_PARAMS, e),
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use serde::Deserialize;
+    use tokio::io::AsyncReadExt;
+
+    use super::*;
+    use crate::SerializableMessage;
+
+    #[derive(Serialize, Deserialize)]
+    struct Add(i64, i64);
+    impl SerializableMessage for Add {
+        fn message_name() -> &'static str {
+            "add"
+        }
+    }
+
+    #[derive(Serialize, Deserialize)]
+    struct Log {
+        line: String,
+    }
+    impl SerializableMessage for Log {
+        fn message_name() -> &'static str {
+            "log"
+        }
+    }
+
+    async fn server() -> JsonRpcServer {
+        let bus = Arc::new(Busstop::new());
+        bus.register_query_fn::<Add, _>(|q: &Add| {
+            let sum = q.0 + q.1;
+            async move { sum }
+        })
+        .await
+        .register_command_middleware::<Log, _>(|mut c, n| {
+            Box::pin(async move {
+                match c.the_command::<Log>().map(|l| l.line.as_str()) {
+                    Some("") => c.fail(DispatchError::Invalid("line is empty".to_string())),
+                    Some("spam") => c.fail(DispatchError::RateLimited),
+                    _ => return n.call(c).await,
+                }
+                c
+            })
+        })
+        .await
+        .register_command_fn::<Log, _>(|_: Log| async {})
+        .await
+        .register_dynamic_query::<Add, i64>()
+        .await
+        .register_dynamic_command::<Log>()
+        .await;
+
+        JsonRpcServer::new(bus)
+    }
+
+    async fn call(server: &JsonRpcServer, request: &str) -> Option<Value> {
+        server
+            .handle(request)
+            .await
+            .map(|r| serde_json::from_str(&r).unwrap())
+    }
+
+    #[tokio::test]
+    async fn test_requests() {
+        let server = server().await;
+
+        let response = call(
+            &server,
+            r#"{"jsonrpc":"2.0","method":"add","params":[2,3],"id":1}"#,
+        )
+        .await;
+        assert_eq!(
+            response,
+            Some(serde_json::json!({"jsonrpc":"2.0","result":5,"id":1}))
+        );
+
+        let response = call(
+            &server,
+            r#"{"jsonrpc":"2.0","method":"log","params":{"line":"hi"},"id":"a"}"#,
+        )
+        .await;
+        assert_eq!(
+            response,
+            Some(serde_json::json!({"jsonrpc":"2.0","result":null,"id":"a"}))
+        );
+
+        let cases = [
+            (
+                r#"{"jsonrpc":"2.0","method":"nope","id":1}"#,
+                METHOD_NOT_FOUND,
+            ),
+            (
+                r#"{"jsonrpc":"2.0","method":"add","params":"x","id":1}"#,
+                INVALID_PARAMS,
+            ),
+            (
+                r#"{"jsonrpc":"2.0","method":"log","params":{"line":""},"id":1}"#,
+                VALIDATION_ERROR,
+            ),
+            (
+                r#"{"jsonrpc":"2.0","method":"log","params":{"line":"spam"},"id":1}"#,
+                RATE_LIMITED,
+            ),
+            (r#"{"method":"add","id":1}"#, INVALID_REQUEST),
+            (r#"{"jsonrpc":"2.0","#, PARSE_ERROR),
+            ("[]", INVALID_REQUEST),
+        ];
+        for (request, code) in cases {
+            let response = call(&server, request).await.unwrap();
+            assert_eq!(response["error"]["code"], code, "{}", request);
+        }
+
+        let notification = r#"{"jsonrpc":"2.0","method":"log","params":{"line":"hi"}}"#;
+        assert_eq!(call(&server, notification).await, None);
+    }
+
+    #[tokio::test]
+    async fn test_batch_over_stream() {
+        let server = server().await;
+        let (mut client, stream) = tokio::io::duplex(1024);
+        let (read, write) = tokio::io::split(stream);
+        let serving = tokio::spawn(async move { server.serve(read, write).await });
+
+        client
+            .write_all(
+                concat!(
+                    r#"[{"jsonrpc":"2.0","method":"add","params":[1,1],"id":1},"#,
+                    r#"{"jsonrpc":"2.0","method":"log","params":{"line":"x"}},"#,
+                    r#"{"jsonrpc":"2.0","method":"add","params":[2,2],"id":2}]"#,
+                    "\n"
+                )
+                .as_bytes(),
+            )
+            .await
+            .unwrap();
+        client.shutdown().await.unwrap();
+
+        let mut output = String::new();
+        client.read_to_string(&mut output).await.unwrap();
+        serving.await.unwrap().unwrap();
+
+        let response: Value = serde_json::from_str(output.trim()).unwrap();
+        assert_eq!(
+            response,
+            serde_json::json!([
+                {"jsonrpc":"2.0","result":2,"id":1},
+                {"jsonrpc":"2.0","result":4,"id":2}
+            ])
+        );
+    }
+}
@@ -76,16 +76,70 @@
 //!   }
 //! }
 //! ```
+mod authorization;
+#[cfg(feature = "auto-register")]
+pub mod auto_register;
 mod busstop;
+mod catch_panic;
 mod command;
+mod dead_letter;
+mod dispatch_error;
+#[cfg(feature = "serde")]
+mod dynamic;
+mod fallback;
+mod family;
+mod gather;
+#[cfg(feature = "http")]
+mod http;
+mod introspection;
+#[cfg(feature = "jsonrpc")]
+pub mod jsonrpc;
+mod metadata;
+mod middleware;
+mod override_guard;
 mod query;
+#[cfg(feature = "tower")]
+pub mod service;
+mod stream;
+#[cfg(feature = "testing")]
+pub mod testing;
+#[cfg(feature = "serde")]
+mod transport;
+mod validation;
+mod variants;
 
 pub use async_trait::async_trait;
+pub use authorization::{Authorize, PRINCIPAL_METADATA_KEY};
 
 pub use busstop::Busstop;
 
 pub use command::*;
+#[cfg(feature = "serde")]
+pub use dead_letter::EncodedCommand;
+#[cfg(feature = "json")]
+pub use dead_letter::FileDeadLetters;
+pub use dead_letter::{
+    DeadLetter, DeadLetterEntry, DeadLetterReason, DeadLetterSink, MemoryDeadLetters,
+    RedispatchError,
+};
+pub use dispatch_error::DispatchError;
+#[cfg(feature = "serde")]
+pub use dynamic::*;
+pub use gather::{Answer, GatheredQuery};
+#[cfg(feature = "http")]
+pub use http::{METADATA_HEADER_PREFIX, Principal};
+pub use introspection::{Introspection, MessageInfo};
+#[cfg(feature = "jsonrpc")]
+pub use jsonrpc::JsonRpcServer;
+pub use metadata::Metadata;
+pub use middleware::Next;
+pub use override_guard::OverrideGuard;
 pub use query::*;
+pub use stream::{QueryStream, QueryStreamFn, QueryStreamFnHandler};
+#[cfg(feature = "serde")]
+pub use transport::*;
+pub use validation::*;
+pub use variants::{CommandVariants, QueryVariants};
 
 #[cfg(test)]
 mod test {
@@ -98,7 +152,7 @@ mod test {
         impl DispatchableCommand for FooCommand {}
 
         let handled = FooCommand.dispatch_command().await;
-        assert_eq!(handled, false);
+        assert!(!handled);
     }
 
     #[tokio::test]
@@ -118,7 +172,7 @@ mod test {
         FooCommand::register_command_handler(FooCommandHandler).await;
 
         let handled = FooCommand.dispatch_command().await;
-        assert_eq!(handled, true);
+        assert!(handled);
     }
 
     #[tokio::test]
@@ -136,11 +190,7 @@ mod test {
             async fn handle_command(&self, dispatched: DispatchedCommand) -> DispatchedCommand {
                 let command = dispatched.the_command::<BroadcastCommand>();
 
-                assert_eq!(
-                    command.is_some(),
-                    true,
-                    "Could not get the dispatched command"
-                );
+                assert!(command.is_some(), "Could not get the dispatched command");
                 assert_eq!(command.as_ref().unwrap().message, "--test--");
                 dispatched
             }
@@ -170,11 +220,7 @@ mod test {
             async fn handle_command(&self, mut dispatched: DispatchedCommand) -> DispatchedCommand {
                 let command = dispatched.the_command_mut::<BroadcastCommand>();
 
-                assert_eq!(
-                    command.is_some(),
-                    true,
-                    "Could not get the dispatched command"
-                );
+                assert!(command.is_some(), "Could not get the dispatched command");
                 assert_eq!(command.as_ref().unwrap().message, "--test--");
 
                 if let Some(inner) = command {
@@ -211,11 +257,7 @@ mod test {
             async fn handle_command(&self, mut dispatched: DispatchedCommand) -> DispatchedCommand {
                 let command = dispatched.take_command::<BroadcastCommand>();
 
-                assert_eq!(
-                    command.is_some(),
-                    true,
-                    "Could not get the dispatched command"
-                );
+                assert!(command.is_some(), "Could not get the dispatched command");
                 assert_eq!(command.as_ref().unwrap().message, "--test--");
 
                 if let Some(mut inner) = command {
@@ -224,7 +266,7 @@ mod test {
                 }
 
                 let command = dispatched.take_command::<BroadcastCommand>();
-                assert_eq!(command.is_none(), true, "Expect none");
+                assert!(command.is_none(), "Expect none");
 
                 dispatched
             }
@@ -247,7 +289,7 @@ mod test {
 
         let dispatched = NextIdQuery.dispatch_query().await;
 
-        assert_eq!(dispatched.handled(), false);
+        assert!(!dispatched.handled());
     }
 
     #[tokio::test]
@@ -268,6 +310,208 @@ mod test {
 
         let dispatched = NextIdQuery.dispatch_query().await;
 
-        assert_eq!(dispatched.handled(), true);
+        assert!(dispatched.handled());
+    }
+
+    #[tokio::test]
+    async fn test_command_fn_handler() {
+        struct PingCommand {
+            count: i32,
+        }
+        impl DispatchableCommand for PingCommand {}
+
+        let seen = std::sync::Arc::new(std::sync::atomic::AtomicI32::new(0));
+        let counter = seen.clone();
+        Busstop::instance()
+            .register_command_fn::<PingCommand, _>(move |cmd: PingCommand| {
+                let counter = counter.clone();
+                async move {
+                    counter.store(cmd.count, std::sync::atomic::Ordering::SeqCst);
+                }
+            })
+            .await;
+
+        assert!(PingCommand { count: 7 }.dispatch_command().await);
+        assert_eq!(seen.load(std::sync::atomic::Ordering::SeqCst), 7);
+    }
+
+    #[tokio::test]
+    async fn test_query_fn_handler() {
+        struct DoubleQuery(i32);
+        impl DispatchableQuery for DoubleQuery {}
+
+        Busstop::instance()
+            .register_query_fn::<DoubleQuery, _>(|q: &DoubleQuery| {
+                let n = q.0;
+                async move { n * 2 }
+            })
+            .await;
+
+        let mut dispatched = DoubleQuery(21).dispatch_query().await;
+
+        assert!(dispatched.handled());
+        assert_eq!(dispatched.take_value::<i32>().map(|v| *v), Some(42));
+    }
+
+    #[tokio::test]
+    async fn test_dispatch_with_metadata() {
+        struct AuditedCommand;
+        impl DispatchableCommand for AuditedCommand {}
+
+        Busstop::instance()
+            .register_command_middleware::<AuditedCommand, _>(|c, n| {
+                Box::pin(async move {
+                    assert_eq!(c.metadata().get("request_id"), Some("abc"));
+                    n.call(c).await
+                })
+            })
+            .await
+            .register_command_fn::<AuditedCommand, _>(|_: AuditedCommand| async {})
+            .await;
+
+        let handled = Busstop::instance()
+            .dispatch_command_with_metadata(
+                AuditedCommand,
+                Metadata::from_iter([("request_id", "abc")]),
+            )
+            .await;
+        assert!(handled);
+    }
+
+    #[tokio::test]
+    async fn test_reentrant_dispatch_and_registration() {
+        struct Outer;
+        struct Inner;
+
+        let bus = std::sync::Arc::new(Busstop::new());
+        let reentrant = bus.clone();
+        bus.register_command_fn::<Outer, _>(move |_: Outer| {
+            let bus = reentrant.clone();
+            async move {
+                if !bus.command_has_handler::<Inner>().await {
+                    bus.register_command_fn::<Inner, _>(|_: Inner| async {})
+                        .await;
+                }
+                bus.register_command_middleware::<Outer, _>(|c, n| Box::pin(n.call(c)))
+                    .await;
+                assert!(bus.dispatch_command(Inner).await);
+                assert!(bus.unregister_command::<Inner>().await);
+            }
+        })
+        .await;
+
+        assert!(bus.dispatch_command(Outer).await);
+        assert!(bus.dispatch_command(Outer).await);
+        assert!(!bus.command_has_handler::<Inner>().await);
+        assert!(!bus.unregister_command::<Inner>().await);
+    }
+
+    #[tokio::test]
+    async fn test_scoped_bus() {
+        struct ScopedCommand;
+        impl DispatchableCommand for ScopedCommand {}
+
+        struct ScopedQuery;
+        impl DispatchableQuery for ScopedQuery {}
+
+        let bus = std::sync::Arc::new(Busstop::new());
+        bus.register_query_fn::<ScopedQuery, _>(|_: &ScopedQuery| async { "scoped" })
+            .await;
+
+        Busstop::scope(bus.clone(), async {
+            ScopedCommand::register_command_handler(CommandFnHandler::new(
+                |_: ScopedCommand| async {},
+            ))
+            .await;
+
+            assert!(ScopedCommand.dispatch_command().await);
+            assert_eq!(
+                ScopedQuery.dispatch_query().await.value::<&str>(),
+                Some(&"scoped")
+            );
+        })
+        .await;
+
+        assert!(bus.command_has_handler::<ScopedCommand>().await);
+        assert!(!ScopedCommand.dispatch_command().await);
+        assert!(ScopedQuery.dispatch_query().await.value::<&str>().is_none());
+    }
+
+    #[tokio::test]
+    async fn test_frozen_bus_routes_changes() {
+        struct Ping;
+        struct Pong;
+        struct Count;
+
+        let bus = Busstop::new();
+        bus.register_command_fn::<Ping, _>(|_: Ping| async {})
+            .await
+            .freeze()
+            .await;
+        assert!(bus.is_frozen());
+        assert!(bus.dispatch_command(Ping).await);
+
+        bus.register_command_fn::<Pong, _>(|_: Pong| async {})
+            .await
+            .register_query_fn::<Count, _>(|_: &Count| async { 1 })
+            .await
+            .register_query_middleware::<Count, _>(|dispatched: DispatchedQuery, next| {
+                Box::pin(async move {
+                    let mut dispatched = next.call(dispatched).await;
+                    dispatched.metadata_mut().insert("seen", "yes");
+                    dispatched
+                })
+            })
+            .await;
+        assert!(bus.dispatch_command(Pong).await);
+        let counted = bus.dispatch_query(Count).await;
+        assert_eq!(counted.value::<i32>(), Some(&1));
+        assert_eq!(counted.metadata().get("seen"), Some("yes"));
+
+        bus.unregister_command::<Ping>().await;
+        assert!(!bus.dispatch_command(Ping).await);
+    }
+
+    #[cfg(feature = "auto-register")]
+    #[tokio::test]
+    async fn test_auto_register() {
+        struct AutoCommand;
+        impl DispatchableCommand for AutoCommand {}
+
+        #[derive(Default)]
+        struct AutoCommandHandler;
+        #[async_trait::async_trait]
+        impl CommandHandler for AutoCommandHandler {
+            async fn handle_command(&self, dispatched: DispatchedCommand) -> DispatchedCommand {
+                dispatched
+            }
+        }
+
+        struct AutoQuery;
+        impl DispatchableQuery for AutoQuery {}
+
+        #[derive(Default)]
+        struct AutoQueryHandler;
+        #[async_trait::async_trait]
+        impl QueryHandler for AutoQueryHandler {
+            async fn handle_query(&self, dispatched: DispatchedQuery) -> DispatchedQuery {
+                dispatched.set_value(true);
+                dispatched
+            }
+        }
+
+        crate::auto_command_handler!(AutoCommand, AutoCommandHandler);
+        crate::auto_query_handler!(AutoQuery, AutoQueryHandler);
+
+        let bus = Busstop::instance();
+        assert!(!bus.command_has_handler::<AutoCommand>().await);
+
+        bus.auto_register().await;
+
+        assert!(AutoCommand.dispatch_command().await);
+        assert_eq!(
+            AutoQuery.dispatch_query().await.value::<bool>(),
+            Some(&true)
+        );
     }
 }
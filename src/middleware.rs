@@ -0,0 +1,132 @@
+use std::sync::Arc;
+
+use arc_swap::ArcSwap;
+use futures::future::BoxFuture;
+
+type Handler<V> = Arc<dyn Fn(V) -> BoxFuture<'static, V> + Send + Sync>;
+type Middleware<V> = Arc<dyn Fn(V, Next<V>) -> BoxFuture<'static, V> + Send + Sync>;
+
+struct Snapshot<V> {
+    handler: Handler<V>,
+    middlewares: Vec<Middleware<V>>,
+}
+
+/// The rest of a middleware chain
+///
+/// Calling it passes the value to the next middleware. After the
+/// last middleware, the value is passed to the handler
+pub struct Next<V> {
+    chain: Arc<Snapshot<V>>,
+    index: usize,
+}
+
+impl<V> std::fmt::Debug for Next<V> {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("Next")
+            .field("index", &self.index)
+            .finish_non_exhaustive()
+    }
+}
+
+impl<V: Send + 'static> Next<V> {
+    /// Passes the value to the next middleware and returns its result
+    pub async fn call(mut self, value: V) -> V {
+        let Some(index) = self.index.checked_sub(1) else {
+            return (self.chain.handler)(value).await;
+        };
+        self.index = index;
+
+        let middleware = Arc::clone(&self.chain.middlewares[index]);
+        (middleware)(value, self).await
+    }
+}
+
+/// A handler and the middlewares wrapped around it
+///
+/// Every run works on a snapshot of the chain. Middlewares added while a
+/// value is going through the chain are used from the next run on
+pub(crate) struct Chain<V> {
+    snapshot: ArcSwap<Snapshot<V>>,
+}
+
+impl<V: Send + 'static> Chain<V> {
+    pub(crate) fn new<H>(handler: H) -> Self
+    where
+        H: Fn(V) -> BoxFuture<'static, V> + Send + Sync + 'static,
+    {
+        Self {
+            snapshot: ArcSwap::from_pointee(Snapshot {
+                handler: Arc::new(handler),
+                middlewares: Vec::new(),
+            }),
+        }
+    }
+
+    /// Adds a middleware. The middleware added last is called first
+    pub(crate) fn push<M>(&self, middleware: M)
+    where
+        M: Fn(V, Next<V>) -> BoxFuture<'static, V> + Send + Sync + 'static,
+    {
+        let middleware: Middleware<V> = Arc::new(middleware);
+        self.snapshot.rcu(|current| {
+            let mut middlewares = current.middlewares.clone();
+            middlewares.push(Arc::clone(&middleware));
+            Snapshot {
+                handler: Arc::clone(&current.handler),
+                middlewares,
+            }
+        });
+    }
+
+    /// Sends the value through a snapshot of the chain
+    pub(crate) async fn run(&self, value: V) -> V {
+        let chain = self.snapshot.load_full();
+        let index = chain.middlewares.len();
+
+        Next { chain, index }.call(value).await
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[tokio::test]
+    async fn test_last_middleware_is_called_first() {
+        let chain = Chain::new(|value: Vec<u8>| Box::pin(async move { value }) as BoxFuture<_>);
+        for id in 1..=3 {
+            chain.push(move |mut value: Vec<u8>, next: Next<Vec<u8>>| {
+                value.push(id);
+                Box::pin(next.call(value)) as BoxFuture<_>
+            });
+        }
+
+        assert_eq!(chain.run(Vec::new()).await, vec![3, 2, 1]);
+    }
+
+    #[tokio::test]
+    async fn test_chain_runs_concurrently() {
+        // Every run waits for the others in the handler
+        let barrier = Arc::new(tokio::sync::Barrier::new(4));
+        let chain = Arc::new(Chain::new(move |value: u32| {
+            let barrier = barrier.clone();
+            Box::pin(async move {
+                barrier.wait().await;
+                value
+            }) as BoxFuture<_>
+        }));
+        chain.push(|value: u32, next: Next<u32>| Box::pin(next.call(value + 1)) as BoxFuture<_>);
+
+        let results = tokio::time::timeout(
+            std::time::Duration::from_secs(5),
+            futures::future::join_all((0..4).map(|v| {
+                let chain = chain.clone();
+                async move { chain.run(v).await }
+            })),
+        )
+        .await
+        .expect("the runs did not go through the chain concurrently");
+
+        assert_eq!(results, vec![1, 2, 3, 4]);
+    }
+}
@@ -0,0 +1,246 @@
+use std::{any::TypeId, collections::HashMap, hash::Hash, sync::Arc};
+
+use crate::{Busstop, CommandHandler, CommandHandlerManager, QueryHandler, QueryHandlerManager};
+
+const LOG_TARGET: &str = "bus_stop";
+
+/// The handlers put in place by `override_command` and `override_query`.
+/// They are looked up before the registered handlers, which are left untouched
+///
+/// Every type keeps a stack of overrides, the last one is in use.
+/// A guard removes its own override wherever it is in the stack, so
+/// guards can be dropped in any order
+#[derive(Default, Clone)]
+pub(crate) struct Overrides {
+    commands: HashMap<TypeId, Vec<Arc<CommandHandlerManager>>>,
+    queries: HashMap<TypeId, Vec<Arc<QueryHandlerManager>>>,
+}
+
+impl Overrides {
+    pub(crate) fn command(&self, type_id: TypeId) -> Option<Arc<CommandHandlerManager>> {
+        self.commands.get(&type_id)?.last().cloned()
+    }
+
+    pub(crate) fn query(&self, type_id: TypeId) -> Option<Arc<QueryHandlerManager>> {
+        self.queries.get(&type_id)?.last().cloned()
+    }
+
+    /// The overrides in use
+    pub(crate) fn commands(&self) -> impl Iterator<Item = (&TypeId, &Arc<CommandHandlerManager>)> {
+        in_use(&self.commands)
+    }
+
+    /// The overrides in use
+    pub(crate) fn queries(&self) -> impl Iterator<Item = (&TypeId, &Arc<QueryHandlerManager>)> {
+        in_use(&self.queries)
+    }
+}
+
+fn in_use<K, V>(map: &HashMap<K, Vec<V>>) -> impl Iterator<Item = (&K, &V)> {
+    map.iter()
+        .filter_map(|(key, stack)| Some((key, stack.last()?)))
+}
+
+enum Installed {
+    Command(Arc<CommandHandlerManager>),
+    Query(Arc<QueryHandlerManager>),
+}
+
+/// Restores the handler that was replaced by `Busstop::override_command`
+/// or `Busstop::override_query`
+///
+/// The original handler, along with its middlewares, is used again when
+/// the guard is dropped or when `restore` is called. When the same type
+/// is overridden again, the latest override is used until its guard is
+/// dropped. Restoring does not lock nor block, the guard can be dropped anywhere.
+#[must_use = "the original handler is restored as soon as the guard is dropped"]
+pub struct OverrideGuard<'a> {
+    bus: &'a Busstop,
+    type_id: TypeId,
+    installed: Option<Installed>,
+}
+
+impl OverrideGuard<'_> {
+    /// Puts the original handler back
+    pub fn restore(self) {
+        drop(self);
+    }
+}
+
+impl Drop for OverrideGuard<'_> {
+    fn drop(&mut self) {
+        let Some(installed) = self.installed.take() else {
+            return;
+        };
+
+        self.bus.overrides.rcu(|current| {
+            let mut overrides = Overrides::clone(current);
+            match &installed {
+                Installed::Command(manager) => {
+                    remove(&mut overrides.commands, self.type_id, manager)
+                }
+                Installed::Query(manager) => remove(&mut overrides.queries, self.type_id, manager),
+            }
+            overrides
+        });
+    }
+}
+
+fn remove<K: Eq + Hash, V>(map: &mut HashMap<K, Vec<Arc<V>>>, key: K, manager: &Arc<V>) {
+    if let Some(stack) = map.get_mut(&key) {
+        stack.retain(|installed| !Arc::ptr_eq(installed, manager));
+        if stack.is_empty() {
+            map.remove(&key);
+        }
+    }
+}
+
+impl Busstop {
+    /// Replaces the handler of the command until the returned guard is dropped
+    ///
+    /// Unlike `register_command`, this does not panic when the command
+    /// already has a handler. The middlewares of the original handler are
+    /// not applied to the override.
+    pub async fn override_command<C: 'static>(
+        &self,
+        handler: impl CommandHandler + 'static,
+    ) -> OverrideGuard<'_> {
+        let name = std::any::type_name::<C>();
+        let manager = Arc::new(CommandHandlerManager::new(handler).await.for_message(name));
+
+        tracing::debug!(target: LOG_TARGET, "overriding command handler for {:?} with {:?}", name, manager.name());
+        let type_id = TypeId::of::<C>();
+        self.overrides.rcu(|current| {
+            let mut overrides = Overrides::clone(current);
+            overrides
+                .commands
+                .entry(type_id)
+                .or_default()
+                .push(Arc::clone(&manager));
+            overrides
+        });
+
+        OverrideGuard {
+            bus: self,
+            type_id,
+            installed: Some(Installed::Command(manager)),
+        }
+    }
+
+    /// Replaces the handler of the query until the returned guard is dropped
+    ///
+    /// Unlike `register_query`, this does not panic when the query
+    /// already has a handler. The middlewares of the original handler are
+    /// not applied to the override.
+    pub async fn override_query<Q: 'static>(
+        &self,
+        handler: impl QueryHandler + 'static,
+    ) -> OverrideGuard<'_> {
+        let name = std::any::type_name::<Q>();
+
+        tracing::debug!(target: LOG_TARGET, "overriding query handler for {:?} with {:?}", name, handler.query_handler_name());
+        let manager = Arc::new(QueryHandlerManager::new(handler).await.for_message(name));
+        let type_id = TypeId::of::<Q>();
+        self.overrides.rcu(|current| {
+            let mut overrides = Overrides::clone(current);
+            overrides
+                .queries
+                .entry(type_id)
+                .or_default()
+                .push(Arc::clone(&manager));
+            overrides
+        });
+
+        OverrideGuard {
+            bus: self,
+            type_id,
+            installed: Some(Installed::Query(manager)),
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::{CommandFnHandler, QueryFnHandler};
+
+    struct Greet;
+
+    struct Ping;
+
+    #[tokio::test]
+    async fn test_override_query_is_restored_on_drop() {
+        let bus = Busstop::new();
+        bus.register_query_fn::<Greet, _>(|_: &Greet| async { "hello" })
+            .await;
+
+        {
+            let _guard = bus
+                .override_query::<Greet>(QueryFnHandler::new(|_: &Greet| async { "fake" }))
+                .await;
+            let result = bus.dispatch_query(Greet).await;
+            assert_eq!(result.value::<&str>(), Some(&"fake"));
+        }
+
+        let result = bus.dispatch_query(Greet).await;
+        assert_eq!(result.value::<&str>(), Some(&"hello"));
+    }
+
+    #[tokio::test]
+    async fn test_override_command_without_original() {
+        let bus = Busstop::new();
+
+        let guard = bus
+            .override_command::<Ping>(CommandFnHandler::new(|_: Ping| async {}))
+            .await;
+        assert!(bus.dispatch_command(Ping).await);
+
+        guard.restore();
+        assert!(!bus.command_has_handler::<Ping>().await);
+        assert!(!bus.dispatch_command(Ping).await);
+    }
+
+    #[tokio::test]
+    async fn test_guard_is_dropped_while_the_registry_is_locked() {
+        let bus = Busstop::new();
+        bus.register_query_fn::<Greet, _>(|_: &Greet| async { "hello" })
+            .await
+            .freeze()
+            .await;
+
+        let guard = bus
+            .override_query::<Greet>(QueryFnHandler::new(|_: &Greet| async { "fake" }))
+            .await;
+        let result = bus.dispatch_query(Greet).await;
+        assert_eq!(result.value::<&str>(), Some(&"fake"));
+
+        let registry = bus.queries.write().await;
+        drop(guard);
+        drop(registry);
+
+        let result = bus.dispatch_query(Greet).await;
+        assert_eq!(result.value::<&str>(), Some(&"hello"));
+    }
+
+    #[tokio::test]
+    async fn test_guards_dropped_out_of_order() {
+        let bus = Busstop::new();
+        bus.register_query_fn::<Greet, _>(|_: &Greet| async { "hello" })
+            .await;
+        let greeting = || async { *bus.dispatch_query(Greet).await.value::<&str>().unwrap() };
+
+        let first = bus
+            .override_query::<Greet>(QueryFnHandler::new(|_: &Greet| async { "first" }))
+            .await;
+        let second = bus
+            .override_query::<Greet>(QueryFnHandler::new(|_: &Greet| async { "second" }))
+            .await;
+        assert_eq!(greeting().await, "second");
+
+        first.restore();
+        assert_eq!(greeting().await, "second");
+
+        drop(second);
+        assert_eq!(greeting().await, "hello");
+    }
+}
@@ -1,19 +1,30 @@
 mod dispatched_query;
+mod query_fn_handler;
 mod query_handler;
 
 use std::sync::Arc;
 
+use arc_swap::ArcSwap;
 pub use dispatched_query::DispatchedQuery;
 use futures::future::BoxFuture;
+pub use query_fn_handler::{QueryFn, QueryFnHandler};
 pub use query_handler::QueryHandler;
-use simple_middleware::{Manager as MiddlewareManager, Next};
 
-use crate::Busstop;
+use crate::{
+    Busstop, DispatchError,
+    catch_panic::catch_panic,
+    middleware::{Chain, Next},
+};
 
-pub type NextQueryMiddleware = Next<DispatchedQuery, DispatchedQuery>;
+const LOG_TARGET: &str = "bus_stop";
+
+/// Next middleware to call. Send argument pass to all queries' middlewares
+///
+/// Since 0.3.0 this is [`crate::Next`], it used to be `simple_middleware::Next`
+pub type NextQueryMiddleware = Next<DispatchedQuery>;
 
 pub type QueryMiddleware = Box<
-    dyn FnMut(DispatchedQuery, NextQueryMiddleware) -> BoxFuture<'static, DispatchedQuery>
+    dyn Fn(DispatchedQuery, NextQueryMiddleware) -> BoxFuture<'static, DispatchedQuery>
         + Send
         + Sync,
 >;
@@ -28,27 +39,43 @@ pub trait DispatchableQuery: Send + Sync {
     where
         Self: Sized + 'static,
     {
-        Busstop::instance().dispatch_query(self).await
+        Busstop::current().dispatch_query(self).await
+    }
+
+    /// Dispatch the query and return the stream of items set by its handler
+    async fn dispatch_query_stream<Item: 'static>(self) -> Option<crate::QueryStream<Item>>
+    where
+        Self: Sized + 'static,
+    {
+        Busstop::current().dispatch_query_stream(self).await
+    }
+
+    /// Send the query to every contributor and gather their answers
+    async fn gather_query(self) -> crate::GatheredQuery
+    where
+        Self: Sized + Clone + 'static,
+    {
+        Busstop::current().gather_query(self).await
     }
 
     /// Register a handler for for this query
     async fn query_handler<H: QueryHandler + Default + 'static>()
     where
-        Self: Sized,
+        Self: Sized + 'static,
     {
-        Busstop::instance()
+        Busstop::current()
             .register_query::<Self>(H::default())
             .await;
     }
 
     async fn query_middleware<M: 'static>(middleware: M)
     where
-        Self: Sized,
-        M: FnMut(DispatchedQuery, NextQueryMiddleware) -> BoxFuture<'static, DispatchedQuery>
+        Self: Sized + 'static,
+        M: Fn(DispatchedQuery, NextQueryMiddleware) -> BoxFuture<'static, DispatchedQuery>
             + Send
             + Sync,
     {
-        Busstop::instance()
+        Busstop::current()
             .register_query_middleware::<Self, M>(middleware)
             .await;
     }
@@ -56,9 +83,9 @@ pub trait DispatchableQuery: Send + Sync {
     /// Register this handler if the query does not have an existing handler
     async fn soft_query_handler<H: QueryHandler + Default + 'static>()
     where
-        Self: Sized,
+        Self: Sized + 'static,
     {
-        let bus = Busstop::instance();
+        let bus = Busstop::current();
         if !bus.query_has_handler::<Self>().await {
             bus.register_query::<Self>(H::default()).await;
         }
@@ -68,18 +95,18 @@ pub trait DispatchableQuery: Send + Sync {
     /// query
     async fn register_query_handler<H: QueryHandler + 'static>(handler: H)
     where
-        Self: Sized,
+        Self: Sized + 'static,
     {
-        Busstop::instance().register_query::<Self>(handler).await;
+        Busstop::current().register_query::<Self>(handler).await;
     }
 
     /// Register the current handler instance as the soft handler of this
     /// query
     async fn register_soft_query_handler<H: QueryHandler + 'static>(handler: H)
     where
-        Self: Sized,
+        Self: Sized + 'static,
     {
-        let bus = Busstop::instance();
+        let bus = Busstop::current();
         if !bus.query_has_handler::<Self>().await {
             bus.register_query::<Self>(handler).await;
         }
@@ -88,25 +115,88 @@ pub trait DispatchableQuery: Send + Sync {
 
 /// Query Handle Manager
 /// Manges the middlewares that will be call before the handler
+///
+/// The manager holds an ordered list of handlers. A handler that declines
+/// the query passes it to the next one
+///
+/// When a handler or a middleware panics, the query fails with
+/// `DispatchError::Panicked` and holds only the metadata
 pub struct QueryHandlerManager {
     name: String,
-    middleware: MiddlewareManager<DispatchedQuery, DispatchedQuery>,
+    message: &'static str,
+    handlers: Arc<ArcSwap<Vec<Arc<dyn QueryHandler>>>>,
+    middleware: Chain<DispatchedQuery>,
 }
 
 impl QueryHandlerManager {
     /// Creates a new instance
     pub async fn new(handler: impl QueryHandler + 'static) -> Self {
-        let handler = Arc::new(Box::new(handler));
+        let name = handler.query_handler_name().to_string();
+        let handlers: Arc<ArcSwap<Vec<Arc<dyn QueryHandler>>>> =
+            Arc::new(ArcSwap::from_pointee(vec![Arc::new(handler)]));
+        let candidates = Arc::clone(&handlers);
+
         Self {
-            name: handler.query_handler_name().to_string(),
-            middleware: MiddlewareManager::last(move |dispatched, _| {
-                let instance = handler.clone();
-                Box::pin(async move { instance.clone().handle_query(dispatched).await })
-            })
-            .await,
+            name,
+            message: "",
+            handlers,
+            middleware: Chain::new(move |mut dispatched: DispatchedQuery| {
+                let candidates = candidates.load_full();
+                Box::pin(async move {
+                    for instance in candidates.iter() {
+                        let name = dispatched.name();
+                        let metadata = dispatched.metadata().clone();
+                        dispatched.declined = false;
+                        dispatched = catch_panic(instance.handle_query(dispatched), |message| {
+                            tracing::error!(target: LOG_TARGET, "handler {:?} panicked while handling query {:?}: {}", instance.query_handler_name(), name, message);
+                            DispatchedQuery::failed(name, metadata, DispatchError::Panicked(message))
+                        })
+                        .await;
+
+                        if !dispatched.declined {
+                            break;
+                        }
+                        tracing::debug!(target: LOG_TARGET, "handler {:?} declined query {:?}", instance.query_handler_name(), name);
+                    }
+
+                    dispatched
+                })
+            }),
         }
     }
 
+    /// Sets the type name of the query the manager handles
+    pub(crate) fn for_message(mut self, message: &'static str) -> Self {
+        self.message = message;
+        self
+    }
+
+    /// The type name of the query, empty when the manager is not registered
+    pub(crate) fn message(&self) -> &'static str {
+        self.message
+    }
+
+    /// The names of the handlers, in the order they are called
+    pub(crate) fn handler_names(&self) -> Vec<&'static str> {
+        self.handlers
+            .load()
+            .iter()
+            .map(|handler| handler.query_handler_name())
+            .collect()
+    }
+
+    /// Adds a handler after the existing ones. It is called when
+    /// the handlers before it decline the query
+    pub async fn add(&self, handler: impl QueryHandler + 'static) -> &Self {
+        let handler: Arc<dyn QueryHandler> = Arc::new(handler);
+        self.handlers.rcu(|current| {
+            let mut handlers = Vec::clone(current);
+            handlers.push(Arc::clone(&handler));
+            handlers
+        });
+        self
+    }
+
     /// Returns the for the Query Handler
     pub fn name(&self) -> &String {
         &self.name
@@ -115,18 +205,25 @@ impl QueryHandlerManager {
     /// Register the next middleware
     pub async fn next<M>(&self, middleware: M) -> &Self
     where
-        M: FnMut(DispatchedQuery, NextQueryMiddleware) -> BoxFuture<'static, DispatchedQuery>
+        M: Fn(DispatchedQuery, NextQueryMiddleware) -> BoxFuture<'static, DispatchedQuery>
             + Send
+            + Sync
             + 'static,
     {
-        self.middleware.next(middleware).await;
+        self.middleware.push(middleware);
         self
     }
 
     /// Handle the specified dispatched query
     pub async fn handle(&self, dispatched: DispatchedQuery) -> DispatchedQuery {
-        let mut result = self.middleware.send(dispatched).await;
-        result.handled = true;
+        let name = dispatched.name();
+        let metadata = dispatched.metadata().clone();
+        let mut result = catch_panic(self.middleware.run(dispatched), |message| {
+            tracing::error!(target: LOG_TARGET, "a middleware of query {:?} panicked: {}", name, message);
+            DispatchedQuery::failed(name, metadata, DispatchError::Panicked(message))
+        })
+        .await;
+        result.handled = !result.declined;
 
         result
     }
@@ -190,4 +287,52 @@ mod test {
 
         assert_eq!(*ans, 22);
     }
+
+    #[tokio::test]
+    async fn test_middleware_panic_is_caught() {
+        let manager = QueryHandlerManager::new(QCommandHandler).await;
+        manager
+            .next(|q: DispatchedQuery, n| {
+                Box::pin(async move {
+                    if q.metadata().contains_key("explode") {
+                        panic!("middleware exploded");
+                    }
+                    n.call(q).await
+                })
+            })
+            .await;
+
+        let query = DispatchedQuery::new(Box::new(1), std::any::type_name::<i32>())
+            .with_metadata(crate::Metadata::from_iter([("explode", "yes")]));
+        let result = manager.handle(query).await;
+        assert_eq!(
+            result.error(),
+            Some(&DispatchError::Panicked("middleware exploded".to_string()))
+        );
+        assert_eq!(result.metadata().get("explode"), Some("yes"));
+
+        let ans = manager.handle_query(1).await.take_value::<i32>().unwrap();
+        assert_eq!(*ans, 1);
+    }
+
+    #[tokio::test]
+    async fn test_middleware_can_decline() {
+        let manager = QueryHandlerManager::new(QCommandHandler).await;
+        manager
+            .next(|mut q: DispatchedQuery, n| {
+                Box::pin(async move {
+                    if q.the_query::<i32>() == Some(&0) {
+                        q.decline();
+                        return q;
+                    }
+                    n.call(q).await
+                })
+            })
+            .await;
+
+        let result = manager.handle_query(0).await;
+        assert!(!result.handled());
+        assert!(result.value::<i32>().is_none());
+        assert!(manager.handle_query(3).await.handled());
+    }
 }
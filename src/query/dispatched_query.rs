@@ -1,25 +1,77 @@
-use std::{any::Any, cell::OnceCell};
+use std::{
+    any::{Any, TypeId},
+    cell::OnceCell,
+};
 
-use crate::DispatchableQuery;
+use crate::{DispatchError, DispatchableQuery, Metadata, family::Memberships};
 
 #[derive(Debug)]
 pub struct DispatchedQuery {
     query: Option<Box<dyn Any + Send + Sync>>,
-    value: OnceCell<(Box<dyn Any + Send + Sync>, String)>,
-    name: String,
+    value: OnceCell<(Box<dyn Any + Send + Sync>, &'static str)>,
+    name: &'static str,
     pub(crate) handled: bool,
+    pub(crate) declined: bool,
+    families: Option<Memberships>,
+    metadata: Metadata,
+    error: Option<DispatchError>,
+    /// The rest of the middleware chain while the query goes through a tower layer
+    #[cfg(feature = "tower")]
+    pub(crate) next: Option<crate::NextQueryMiddleware>,
 }
 
 impl DispatchedQuery {
-    pub(crate) fn new(query: Box<dyn Any + Send + Sync>, name: &str) -> Self {
+    pub(crate) fn new(query: Box<dyn Any + Send + Sync>, name: &'static str) -> Self {
         Self {
             query: Some(query),
             value: OnceCell::new(),
             handled: false,
-            name: name.to_string(),
+            declined: false,
+            families: None,
+            name,
+            metadata: Metadata::new(),
+            error: None,
+            #[cfg(feature = "tower")]
+            next: None,
         }
     }
 
+    /// Creates a failed query that no longer holds the query. Used when the
+    /// original was consumed by something that failed
+    pub(crate) fn failed(name: &'static str, metadata: Metadata, error: DispatchError) -> Self {
+        let mut dispatched = Self::new(Box::new(()), name).with_metadata(metadata);
+        dispatched.query = None;
+        dispatched.error = Some(error);
+        dispatched
+    }
+
+    /// The type id of the query, if it has not been taken
+    pub(crate) fn message_type_id(&self) -> Option<TypeId> {
+        self.query.as_deref().map(Any::type_id)
+    }
+
+    /// The boxed query, if it has not been taken
+    pub(crate) fn boxed(&self) -> Option<&(dyn Any + Send + Sync)> {
+        self.query.as_deref()
+    }
+
+    /// Takes the boxed query without knowing its type
+    #[cfg_attr(not(feature = "testing"), allow(dead_code))]
+    pub(crate) fn take_boxed(&mut self) -> Option<Box<dyn Any + Send + Sync>> {
+        self.query.take()
+    }
+
+    pub(crate) fn with_metadata(mut self, metadata: Metadata) -> Self {
+        self.metadata = metadata;
+        self
+    }
+
+    /// Sets the families the message was routed through
+    pub(crate) fn with_families(mut self, families: Memberships) -> Self {
+        self.families = Some(families);
+        self
+    }
+
     /// Returns a reference (the real query) of the dispatched query
     pub fn the_query<T: 'static>(&self) -> Option<&T> {
         if let Some(query) = &self.query {
@@ -29,6 +81,13 @@ impl DispatchedQuery {
         }
     }
 
+    /// Returns the query as the trait object `T` of the family it was routed through.
+    /// Returns `None` when the query was not routed to a family handler
+    /// or is not a member of the family `T`
+    pub fn as_dyn<T: ?Sized + 'static>(&self) -> Option<&T> {
+        crate::family::cast(self.families.as_ref()?, self.query.as_deref()?)
+    }
+
     /// Returns a mutable reference to the query
     pub fn the_query_mut<T: 'static>(&mut self) -> Option<&mut T> {
         if let Some(query) = &mut self.query {
@@ -51,7 +110,7 @@ impl DispatchedQuery {
     /// Sets the value that will be returned to the dispatcher
     pub fn set_value<V: Send + Sync + 'static>(&self, value: V) {
         let x = std::any::type_name::<V>();
-        if self.value.set((Box::new(value), x.to_string())).is_err() {
+        if self.value.set((Box::new(value), x)).is_err() {
             tracing::error!(target: "dispatched query", "value can only be set once. Query: {}", &self.name);
         }
     }
@@ -79,8 +138,51 @@ impl DispatchedQuery {
         self.handled
     }
 
-    pub fn name(&self) -> &String {
-        &self.name
+    pub fn name(&self) -> &'static str {
+        self.name
+    }
+
+    /// Tells the bus that this handler does not handle this query.
+    /// The query is passed to the next handler registered for the type.
+    /// When every handler declines, the query is not handled
+    ///
+    /// A middleware that declines and returns without calling the next
+    /// middleware ends the chain, no handler is called and the query
+    /// is not handled
+    pub fn decline(&mut self) {
+        self.declined = true;
+    }
+
+    /// Returns true if the last handler declined the query
+    pub fn declined(&self) -> bool {
+        self.declined
+    }
+
+    /// Marks the query as failed
+    /// A handler or middleware that fails should return without
+    /// calling the next middleware
+    pub fn fail(&mut self, error: DispatchError) {
+        self.error = Some(error);
+    }
+
+    /// Returns the reason the query failed
+    pub fn error(&self) -> Option<&DispatchError> {
+        self.error.as_ref()
+    }
+
+    /// Returns true if the query was marked as failed
+    pub fn is_failed(&self) -> bool {
+        self.error.is_some()
+    }
+
+    /// The metadata passed along with the query
+    pub fn metadata(&self) -> &Metadata {
+        &self.metadata
+    }
+
+    /// Returns a mutable reference to the metadata
+    pub fn metadata_mut(&mut self) -> &mut Metadata {
+        &mut self.metadata
     }
 
     /// Compares the type of Q with this dispatched query type
@@ -91,7 +193,7 @@ impl DispatchedQuery {
     /// Compares the type of the value with the type of T
     pub fn value_type_is<T>(&self) -> bool {
         if let Some((_, name)) = self.value.get() {
-            std::any::type_name::<T>() == name
+            std::any::type_name::<T>() == *name
         } else {
             false
         }
@@ -100,7 +202,6 @@ impl DispatchedQuery {
 
 impl<Q: DispatchableQuery + 'static> From<Q> for DispatchedQuery {
     fn from(value: Q) -> Self {
-        let name = std::any::type_name::<Q>().to_string();
-        Self::new(Box::new(value), &name)
+        Self::new(Box::new(value), std::any::type_name::<Q>())
     }
 }
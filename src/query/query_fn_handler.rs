@@ -0,0 +1,77 @@
+use std::{future::Future, marker::PhantomData};
+
+use futures::future::BoxFuture;
+
+use super::{DispatchedQuery, query_handler::QueryHandler};
+
+/// An async function or closure that can answer a query
+///
+/// This trait is implemented for every `Fn(&Q) -> impl Future<Output = V>`.
+/// The returned future cannot borrow the query; copy or clone what
+/// is needed before the `async move` block.
+pub trait QueryFn<Q>: Send + Sync + 'static {
+    /// The type of the value returned to the dispatcher
+    type Value: Send + Sync + 'static;
+
+    /// Calls the function with a reference to the query
+    fn call(&self, query: &Q) -> BoxFuture<'static, Self::Value>;
+}
+
+impl<Q, F, Fut> QueryFn<Q> for F
+where
+    F: Fn(&Q) -> Fut + Send + Sync + 'static,
+    Fut: Future + Send + 'static,
+    Fut::Output: Send + Sync + 'static,
+{
+    type Value = Fut::Output;
+
+    fn call(&self, query: &Q) -> BoxFuture<'static, Self::Value> {
+        Box::pin((self)(query))
+    }
+}
+
+/// Wraps a plain async function or closure so that it can be used
+/// as a query handler.
+///
+/// The value the function returns is set as the value of the dispatched query.
+/// The function cannot decline, every query it receives is handled.
+pub struct QueryFnHandler<Q, F> {
+    handler: F,
+    _query: PhantomData<fn(Q)>,
+}
+
+impl<Q, F: QueryFn<Q>> QueryFnHandler<Q, F> {
+    /// Create a new instance
+    pub fn new(handler: F) -> Self {
+        Self {
+            handler,
+            _query: PhantomData,
+        }
+    }
+}
+
+#[async_trait::async_trait]
+impl<Q, F> QueryHandler for QueryFnHandler<Q, F>
+where
+    Q: Send + Sync + 'static,
+    F: QueryFn<Q>,
+{
+    async fn handle_query(&self, dispatched: DispatchedQuery) -> DispatchedQuery {
+        let future = match dispatched.the_query::<Q>() {
+            Some(query) => self.handler.call(query),
+            None => {
+                tracing::error!(target: "dispatched query", "query {} has already been taken", dispatched.name());
+                return dispatched;
+            }
+        };
+
+        let value = future.await;
+        dispatched.set_value(value);
+
+        dispatched
+    }
+
+    fn query_handler_name(&self) -> &'static str {
+        std::any::type_name::<F>()
+    }
+}
@@ -0,0 +1,430 @@
+//! Interoperability with `tower`
+//!
+//! The bus can be used as a tower [`Service`], tower layers can be
+//! registered as middlewares and tower services can be registered as handlers.
+use std::{
+    convert::Infallible,
+    sync::Arc,
+    task::{Context, Poll},
+};
+
+use futures::future::{BoxFuture, poll_fn};
+use tokio::sync::Mutex;
+use tower_layer::Layer;
+use tower_service::Service;
+
+use crate::{
+    Busstop, CommandHandler, DispatchError, DispatchedCommand, DispatchedQuery,
+    NextCommandMiddleware, NextQueryMiddleware, QueryHandler,
+};
+
+/// Boxed error used by the services in this module
+pub type BoxError = Box<dyn std::error::Error + Send + Sync>;
+
+const LOG_TARGET: &str = "bus_stop";
+
+/// Routes dispatched commands through the bus
+#[derive(Clone)]
+pub struct CommandService {
+    bus: Arc<Busstop>,
+}
+
+impl CommandService {
+    /// Create a new instance
+    pub fn new(bus: Arc<Busstop>) -> Self {
+        Self { bus }
+    }
+}
+
+impl Service<DispatchedCommand> for CommandService {
+    type Response = DispatchedCommand;
+    type Error = Infallible;
+    type Future = BoxFuture<'static, Result<DispatchedCommand, Infallible>>;
+
+    fn poll_ready(&mut self, _cx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+        Poll::Ready(Ok(()))
+    }
+
+    fn call(&mut self, dispatched: DispatchedCommand) -> Self::Future {
+        let bus = Arc::clone(&self.bus);
+        Box::pin(async move { Ok(bus.route_command(dispatched).await) })
+    }
+}
+
+/// Routes dispatched queries through the bus
+#[derive(Clone)]
+pub struct QueryService {
+    bus: Arc<Busstop>,
+}
+
+impl QueryService {
+    /// Create a new instance
+    pub fn new(bus: Arc<Busstop>) -> Self {
+        Self { bus }
+    }
+}
+
+impl Service<DispatchedQuery> for QueryService {
+    type Response = DispatchedQuery;
+    type Error = Infallible;
+    type Future = BoxFuture<'static, Result<DispatchedQuery, Infallible>>;
+
+    fn poll_ready(&mut self, _cx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+        Poll::Ready(Ok(()))
+    }
+
+    fn call(&mut self, dispatched: DispatchedQuery) -> Self::Future {
+        let bus = Arc::clone(&self.bus);
+        Box::pin(async move { Ok(bus.route_query(dispatched).await) })
+    }
+}
+
+/// The rest of a command's middleware chain, as a tower service
+///
+/// This is the service a layer passed to [`command_layer`] wraps. It passes
+/// each command to the rest of the chain it was dispatched through
+#[derive(Debug, Clone)]
+pub struct NextCommandService(());
+
+impl Service<DispatchedCommand> for NextCommandService {
+    type Response = DispatchedCommand;
+    type Error = BoxError;
+    type Future = BoxFuture<'static, Result<DispatchedCommand, BoxError>>;
+
+    fn poll_ready(&mut self, _cx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+        Poll::Ready(Ok(()))
+    }
+
+    fn call(&mut self, mut dispatched: DispatchedCommand) -> Self::Future {
+        Box::pin(async move {
+            match dispatched.next.take() {
+                Some(next) => Ok(next.call(dispatched).await),
+                None => Err("the command is not going through a middleware chain".into()),
+            }
+        })
+    }
+}
+
+/// The rest of a query's middleware chain, as a tower service
+///
+/// See [`NextCommandService`]
+#[derive(Debug, Clone)]
+pub struct NextQueryService(());
+
+impl Service<DispatchedQuery> for NextQueryService {
+    type Response = DispatchedQuery;
+    type Error = BoxError;
+    type Future = BoxFuture<'static, Result<DispatchedQuery, BoxError>>;
+
+    fn poll_ready(&mut self, _cx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+        Poll::Ready(Ok(()))
+    }
+
+    fn call(&mut self, mut dispatched: DispatchedQuery) -> Self::Future {
+        Box::pin(async move {
+            match dispatched.next.take() {
+                Some(next) => Ok(next.call(dispatched).await),
+                None => Err("the query is not going through a middleware chain".into()),
+            }
+        })
+    }
+}
+
+/// Turns a tower layer into a command middleware
+///
+/// The layered service is built once and shared by every dispatch, so
+/// stateful layers such as concurrency and rate limits hold across dispatches.
+/// When the layered service fails, the command fails with `DispatchError::Failed`
+///
+/// ```rust
+/// use std::time::Duration;
+///
+/// use busstop::{Busstop, DispatchableCommand, service::command_layer};
+/// use tower::timeout::TimeoutLayer;
+///
+/// struct CreateUser;
+/// impl DispatchableCommand for CreateUser {}
+///
+/// # #[tokio::main]
+/// # async fn main() {
+/// let bus = Busstop::new();
+/// bus.register_command_middleware::<CreateUser, _>(command_layer(TimeoutLayer::new(
+///     Duration::from_secs(5),
+/// )))
+/// .await;
+/// # }
+/// ```
+pub fn command_layer<L>(
+    layer: L,
+) -> impl Fn(DispatchedCommand, NextCommandMiddleware) -> BoxFuture<'static, DispatchedCommand>
++ Send
++ Sync
++ 'static
+where
+    L: Layer<NextCommandService>,
+    L::Service: Service<DispatchedCommand, Response = DispatchedCommand> + Send + 'static,
+    <L::Service as Service<DispatchedCommand>>::Error: Into<BoxError>,
+    <L::Service as Service<DispatchedCommand>>::Future: Send + 'static,
+{
+    let service = Arc::new(Mutex::new(layer.layer(NextCommandService(()))));
+
+    move |mut dispatched, next| {
+        let service = Arc::clone(&service);
+        let name = dispatched.name();
+        let metadata = dispatched.metadata().clone();
+        dispatched.next = Some(next);
+
+        Box::pin(async move {
+            match call_shared(&service, dispatched).await {
+                Ok(dispatched) => dispatched,
+                Err(e) => {
+                    tracing::error!(target: LOG_TARGET, "command {:?} failed in a layer: {}", name, e);
+                    DispatchedCommand::failed(name, metadata, DispatchError::Failed(e.to_string()))
+                }
+            }
+        })
+    }
+}
+
+/// Turns a tower layer into a query middleware
+///
+/// See [`command_layer`]
+pub fn query_layer<L>(
+    layer: L,
+) -> impl Fn(DispatchedQuery, NextQueryMiddleware) -> BoxFuture<'static, DispatchedQuery>
++ Send
++ Sync
++ 'static
+where
+    L: Layer<NextQueryService>,
+    L::Service: Service<DispatchedQuery, Response = DispatchedQuery> + Send + 'static,
+    <L::Service as Service<DispatchedQuery>>::Error: Into<BoxError>,
+    <L::Service as Service<DispatchedQuery>>::Future: Send + 'static,
+{
+    let service = Arc::new(Mutex::new(layer.layer(NextQueryService(()))));
+
+    move |mut dispatched, next| {
+        let service = Arc::clone(&service);
+        let name = dispatched.name();
+        let metadata = dispatched.metadata().clone();
+        dispatched.next = Some(next);
+
+        Box::pin(async move {
+            match call_shared(&service, dispatched).await {
+                Ok(dispatched) => dispatched,
+                Err(e) => {
+                    tracing::error!(target: LOG_TARGET, "query {:?} failed in a layer: {}", name, e);
+                    DispatchedQuery::failed(name, metadata, DispatchError::Failed(e.to_string()))
+                }
+            }
+        })
+    }
+}
+
+/// Uses a tower service as the handler of a command
+///
+/// The service is cloned for every dispatch
+pub struct CommandServiceHandler<S> {
+    service: S,
+}
+
+impl<S> CommandServiceHandler<S> {
+    /// Create a new instance
+    pub fn new(service: S) -> Self {
+        Self { service }
+    }
+}
+
+#[async_trait::async_trait]
+impl<S> CommandHandler for CommandServiceHandler<S>
+where
+    S: Service<DispatchedCommand, Response = DispatchedCommand> + Clone + Send + Sync + 'static,
+    S::Error: Into<BoxError>,
+    S::Future: Send,
+{
+    async fn handle_command(&self, dispatched: DispatchedCommand) -> DispatchedCommand {
+        let name = dispatched.name();
+        let metadata = dispatched.metadata().clone();
+
+        match call_service(self.service.clone(), dispatched).await {
+            Ok(dispatched) => dispatched,
+            Err(e) => {
+                tracing::error!(target: LOG_TARGET, "service handling command {:?} failed: {}", name, e);
+                DispatchedCommand::failed(name, metadata, DispatchError::Failed(e.to_string()))
+            }
+        }
+    }
+
+    fn command_handler_name(&self) -> &'static str {
+        std::any::type_name::<S>()
+    }
+}
+
+/// Uses a tower service as the handler of a query
+///
+/// The service is cloned for every dispatch
+pub struct QueryServiceHandler<S> {
+    service: S,
+}
+
+impl<S> QueryServiceHandler<S> {
+    /// Create a new instance
+    pub fn new(service: S) -> Self {
+        Self { service }
+    }
+}
+
+#[async_trait::async_trait]
+impl<S> QueryHandler for QueryServiceHandler<S>
+where
+    S: Service<DispatchedQuery, Response = DispatchedQuery> + Clone + Send + Sync + 'static,
+    S::Error: Into<BoxError>,
+    S::Future: Send,
+{
+    async fn handle_query(&self, dispatched: DispatchedQuery) -> DispatchedQuery {
+        let name = dispatched.name();
+        let metadata = dispatched.metadata().clone();
+
+        match call_service(self.service.clone(), dispatched).await {
+            Ok(dispatched) => dispatched,
+            Err(e) => {
+                tracing::error!(target: LOG_TARGET, "service handling query {:?} failed: {}", name, e);
+                DispatchedQuery::failed(name, metadata, DispatchError::Failed(e.to_string()))
+            }
+        }
+    }
+
+    fn query_handler_name(&self) -> &'static str {
+        std::any::type_name::<S>()
+    }
+}
+
+async fn call_service<S, R>(mut service: S, request: R) -> Result<S::Response, BoxError>
+where
+    S: Service<R>,
+    S::Error: Into<BoxError>,
+{
+    poll_fn(|cx| service.poll_ready(cx))
+        .await
+        .map_err(Into::into)?;
+    service.call(request).await.map_err(Into::into)
+}
+
+/// Calls a service shared between dispatches. The lock is only held until
+/// the service accepted the request
+async fn call_shared<S, R>(service: &Mutex<S>, request: R) -> Result<S::Response, BoxError>
+where
+    S: Service<R>,
+    S::Error: Into<BoxError>,
+{
+    let response = {
+        let mut service = service.lock().await;
+        poll_fn(|cx| service.poll_ready(cx))
+            .await
+            .map_err(Into::into)?;
+        service.call(request)
+    };
+    response.await.map_err(Into::into)
+}
+
+#[cfg(test)]
+mod test {
+    use std::time::Duration;
+
+    use std::sync::atomic::{AtomicUsize, Ordering};
+
+    use tower::{ServiceExt, limit::ConcurrencyLimitLayer, service_fn, timeout::TimeoutLayer};
+
+    use super::*;
+    use crate::{DispatchableCommand, DispatchableQuery};
+
+    struct Resize;
+    impl DispatchableCommand for Resize {}
+
+    struct Add(i32, i32);
+    impl DispatchableQuery for Add {}
+
+    struct SlowAdd(i32, i32);
+    impl DispatchableQuery for SlowAdd {}
+
+    #[tokio::test]
+    async fn test_query_service() {
+        let bus = Arc::new(Busstop::new());
+        bus.register_query_fn::<Add, _>(|q: &Add| {
+            let sum = q.0 + q.1;
+            async move { sum }
+        })
+        .await;
+
+        let dispatched = QueryService::new(Arc::clone(&bus))
+            .oneshot(DispatchedQuery::new(
+                Box::new(SlowAdd(2, 3)),
+                std::any::type_name::<SlowAdd>(),
+            ))
+            .await
+            .unwrap();
+        assert!(dispatched.value::<i32>().is_none());
+
+        let dispatched = QueryService::new(bus)
+            .oneshot(DispatchedQuery::new(
+                Box::new(Add(2, 3)),
+                std::any::type_name::<Add>(),
+            ))
+            .await
+            .unwrap();
+        assert_eq!(dispatched.value::<i32>(), Some(&5));
+    }
+
+    #[tokio::test]
+    async fn test_service_handler_and_layer() {
+        let bus = Busstop::new();
+        let service = service_fn(|dispatched: DispatchedQuery| async move {
+            let (a, b) = match dispatched.the_query::<SlowAdd>() {
+                Some(q) => (q.0, q.1),
+                None => return Err::<_, BoxError>("not an add".into()),
+            };
+            if a > 100 {
+                tokio::time::sleep(Duration::from_millis(200)).await;
+            }
+            dispatched.set_value(a + b);
+            Ok(dispatched)
+        });
+        bus.register_query::<SlowAdd>(QueryServiceHandler::new(service))
+            .await;
+        bus.register_query_middleware::<SlowAdd, _>(query_layer(TimeoutLayer::new(
+            Duration::from_millis(50),
+        )))
+        .await;
+
+        let dispatched = bus.dispatch_query(SlowAdd(1, 2)).await;
+        assert_eq!(dispatched.value::<i32>(), Some(&3));
+
+        let dispatched = bus.dispatch_query(SlowAdd(101, 2)).await;
+        assert!(dispatched.value::<i32>().is_none());
+        assert!(matches!(dispatched.error(), Some(DispatchError::Failed(_))));
+    }
+
+    #[tokio::test]
+    async fn test_concurrency_limit_holds_across_dispatches() {
+        static RUNNING: AtomicUsize = AtomicUsize::new(0);
+        static MOST_RUNNING: AtomicUsize = AtomicUsize::new(0);
+
+        let bus = Busstop::new();
+        bus.register_command_fn::<Resize, _>(|_: Resize| async {
+            let running = RUNNING.fetch_add(1, Ordering::SeqCst) + 1;
+            MOST_RUNNING.fetch_max(running, Ordering::SeqCst);
+            for _ in 0..10 {
+                tokio::task::yield_now().await;
+            }
+            RUNNING.fetch_sub(1, Ordering::SeqCst);
+        })
+        .await;
+        bus.register_command_middleware::<Resize, _>(command_layer(ConcurrencyLimitLayer::new(2)))
+            .await;
+
+        let handled = futures::future::join_all((0..6).map(|_| bus.dispatch_command(Resize))).await;
+
+        assert!(handled.into_iter().all(|handled| handled));
+        assert_eq!(MOST_RUNNING.load(Ordering::SeqCst), 2);
+    }
+}
@@ -0,0 +1,271 @@
+use std::{marker::PhantomData, sync::Mutex};
+
+use futures::{Stream, StreamExt, stream::BoxStream};
+
+use crate::{Busstop, DispatchedQuery, Metadata, QueryHandler};
+
+const LOG_TARGET: &str = "bus_stop";
+
+/// The stream of items returned by a streaming query
+///
+/// Items are produced as the stream is polled. A slow consumer slows
+/// the handler down, nothing is buffered in between
+pub type QueryStream<Item> = BoxStream<'static, Item>;
+
+/// Holds the stream as the value of the query. The value of a query
+/// must be `Sync`, streams usually are not
+struct StreamSlot<Item>(Mutex<QueryStream<Item>>);
+
+impl DispatchedQuery {
+    /// Sets a stream of items as the value that will be returned to the dispatcher
+    pub fn set_stream<S>(&self, stream: S)
+    where
+        S: Stream + Send + 'static,
+        S::Item: Send + 'static,
+    {
+        self.set_value(StreamSlot(Mutex::new(stream.boxed())));
+    }
+
+    /// Returns true if the value is a stream of `Item`
+    pub fn has_stream<Item: 'static>(&self) -> bool {
+        self.value::<StreamSlot<Item>>().is_some()
+    }
+
+    /// Returns the stream set by the handler of the query
+    pub fn take_stream<Item: 'static>(&mut self) -> Option<QueryStream<Item>> {
+        if !self.has_stream::<Item>() {
+            return None;
+        }
+
+        self.take_value::<StreamSlot<Item>>()
+            .map(|slot| slot.0.into_inner().unwrap_or_else(|e| e.into_inner()))
+    }
+
+    /// Replaces the stream of `Item` with the stream returned by `f`.
+    /// Middlewares use this to observe or transform the items after
+    /// calling the next middleware.
+    /// Returns false if the value is not a stream of `Item`
+    ///
+    /// ```rust,ignore
+    /// bus.register_query_middleware::<ListUsers, _>(|dispatched, next| {
+    ///     Box::pin(async move {
+    ///         let mut dispatched = next.call(dispatched).await;
+    ///         dispatched.map_stream(|users: QueryStream<User>| users.filter(|u| ready(u.active)));
+    ///         dispatched
+    ///     })
+    /// })
+    /// .await;
+    /// ```
+    pub fn map_stream<Item, S>(&mut self, f: impl FnOnce(QueryStream<Item>) -> S) -> bool
+    where
+        Item: 'static,
+        S: Stream + Send + 'static,
+        S::Item: Send + 'static,
+    {
+        match self.take_stream::<Item>() {
+            Some(stream) => {
+                self.set_stream(f(stream));
+                true
+            }
+            None => false,
+        }
+    }
+}
+
+/// A function or closure that answers a query with a stream of items
+///
+/// This trait is implemented for every `Fn(&Q) -> impl Stream`.
+/// Like [`crate::QueryFn`], the returned stream cannot borrow the query
+pub trait QueryStreamFn<Q>: Send + Sync + 'static {
+    /// The type of the items in the stream
+    type Item: Send + 'static;
+
+    /// Calls the function with a reference to the query
+    fn call(&self, query: &Q) -> QueryStream<Self::Item>;
+}
+
+impl<Q, F, S> QueryStreamFn<Q> for F
+where
+    F: Fn(&Q) -> S + Send + Sync + 'static,
+    S: Stream + Send + 'static,
+    S::Item: Send + 'static,
+{
+    type Item = S::Item;
+
+    fn call(&self, query: &Q) -> QueryStream<Self::Item> {
+        (self)(query).boxed()
+    }
+}
+
+/// Wraps a function or closure that returns a stream so that it can be used
+/// as a query handler
+///
+/// The stream is set as the value of the dispatched query.
+pub struct QueryStreamFnHandler<Q, F> {
+    handler: F,
+    _query: PhantomData<fn(Q)>,
+}
+
+impl<Q, F: QueryStreamFn<Q>> QueryStreamFnHandler<Q, F> {
+    /// Create a new instance
+    pub fn new(handler: F) -> Self {
+        Self {
+            handler,
+            _query: PhantomData,
+        }
+    }
+}
+
+#[async_trait::async_trait]
+impl<Q, F> QueryHandler for QueryStreamFnHandler<Q, F>
+where
+    Q: Send + Sync + 'static,
+    F: QueryStreamFn<Q>,
+{
+    async fn handle_query(&self, dispatched: DispatchedQuery) -> DispatchedQuery {
+        match dispatched.the_query::<Q>() {
+            Some(query) => dispatched.set_stream(self.handler.call(query)),
+            None => {
+                tracing::error!(target: "dispatched query", "query {} has already been taken", dispatched.name());
+            }
+        }
+
+        dispatched
+    }
+
+    fn query_handler_name(&self) -> &'static str {
+        std::any::type_name::<F>()
+    }
+}
+
+impl Busstop {
+    /// Register a function or closure that returns a stream as the handler for a query
+    pub async fn register_query_stream_fn<Q, F>(&self, handler: F) -> &Self
+    where
+        Q: Send + Sync + 'static,
+        F: QueryStreamFn<Q>,
+    {
+        self.register_query::<Q>(QueryStreamFnHandler::new(handler))
+            .await
+    }
+
+    /// Dispatches a query and returns the stream of items set by its handler.
+    /// Returns `None` when the query was not handled, failed or its value
+    /// is not a stream of `Item`
+    pub async fn dispatch_query_stream<Q, Item>(&self, query: Q) -> Option<QueryStream<Item>>
+    where
+        Q: Send + Sync + 'static,
+        Item: 'static,
+    {
+        self.dispatch_query_stream_with_metadata(query, Metadata::new())
+            .await
+    }
+
+    /// Same as `dispatch_query_stream` but passes the metadata along with the query
+    pub async fn dispatch_query_stream_with_metadata<Q, Item>(
+        &self,
+        query: Q,
+        metadata: Metadata,
+    ) -> Option<QueryStream<Item>>
+    where
+        Q: Send + Sync + 'static,
+        Item: 'static,
+    {
+        let mut dispatched = self.dispatch_query_with_metadata(query, metadata).await;
+        if dispatched.is_failed() {
+            return None;
+        }
+
+        let stream = dispatched.take_stream::<Item>();
+        if stream.is_none() && dispatched.handled() {
+            tracing::error!(target: LOG_TARGET, "query {:?} did not return a stream of {:?}", dispatched.name(), std::any::type_name::<Item>());
+        }
+
+        stream
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::sync::{
+        Arc,
+        atomic::{AtomicUsize, Ordering},
+    };
+
+    use futures::{future::ready, stream};
+
+    use super::*;
+    use crate::DispatchError;
+
+    struct ListNumbers {
+        up_to: usize,
+    }
+
+    #[tokio::test]
+    async fn test_stream_is_pulled_by_the_caller() {
+        let produced = Arc::new(AtomicUsize::new(0));
+        let counter = produced.clone();
+
+        let bus = Busstop::new();
+        bus.register_query_stream_fn::<ListNumbers, _>(move |q: &ListNumbers| {
+            let counter = counter.clone();
+            stream::iter(0..q.up_to).inspect(move |_| {
+                counter.fetch_add(1, Ordering::SeqCst);
+            })
+        })
+        .await;
+
+        let numbers = bus
+            .dispatch_query_stream::<_, usize>(ListNumbers { up_to: 10_000 })
+            .await
+            .unwrap();
+        assert_eq!(produced.load(Ordering::SeqCst), 0);
+
+        let first = numbers.take(3).collect::<Vec<_>>().await;
+        assert_eq!(first, vec![0, 1, 2]);
+        assert_eq!(produced.load(Ordering::SeqCst), 3);
+
+        assert!(
+            bus.dispatch_query_stream::<_, String>(ListNumbers { up_to: 1 })
+                .await
+                .is_none()
+        );
+    }
+
+    #[tokio::test]
+    async fn test_middleware_transforms_stream() {
+        let bus = Busstop::new();
+        bus.register_query_stream_fn::<ListNumbers, _>(|q: &ListNumbers| stream::iter(0..q.up_to))
+            .await
+            .register_query_middleware::<ListNumbers, _>(|dispatched, next| {
+                Box::pin(async move {
+                    if dispatched.metadata().contains_key("deny") {
+                        let mut dispatched = dispatched;
+                        dispatched.fail(DispatchError::Invalid("denied".to_string()));
+                        return dispatched;
+                    }
+
+                    let mut dispatched = next.call(dispatched).await;
+                    dispatched.map_stream(|numbers: QueryStream<usize>| {
+                        numbers.filter(|n| ready(n % 2 == 0)).map(|n| n * 10)
+                    });
+                    dispatched
+                })
+            })
+            .await;
+
+        let numbers = bus
+            .dispatch_query_stream::<_, usize>(ListNumbers { up_to: 6 })
+            .await
+            .unwrap();
+        assert_eq!(numbers.collect::<Vec<_>>().await, vec![0, 20, 40]);
+
+        let denied = bus
+            .dispatch_query_stream_with_metadata::<_, usize>(
+                ListNumbers { up_to: 6 },
+                Metadata::from_iter([("deny", "yes")]),
+            )
+            .await;
+        assert!(denied.is_none());
+    }
+}
@@ -0,0 +1,319 @@
+//! Helpers for testing code that dispatches commands and queries
+//!
+//! A [`RecordingBus`] is an isolated bus that records every command and
+//! query dispatched through it instead of handling them. Queries can be
+//! answered with canned values.
+//!
+//! ```rust
+//! use busstop::testing::RecordingBus;
+//!
+//! struct CreateUser {
+//!     email: String,
+//! }
+//!
+//! struct CountUsers;
+//!
+//! # #[tokio::main(flavor = "current_thread")]
+//! # async fn main() {
+//! let recording = RecordingBus::new();
+//! recording.stub_query::<CountUsers, _>(3_usize);
+//!
+//! // Code under test
+//! recording.bus().dispatch_command(CreateUser { email: "ada@example.com".to_string() }).await;
+//! let count = recording.bus().dispatch_query(CountUsers).await;
+//!
+//! recording.assert_dispatched::<CreateUser>(|c| c.email == "ada@example.com");
+//! recording.assert_queried::<CountUsers>(|_| true);
+//! assert_eq!(count.value::<usize>(), Some(&3));
+//! # }
+//! ```
+use std::{
+    any::Any,
+    collections::HashMap,
+    sync::{Arc, Mutex},
+};
+
+use crate::{Busstop, DispatchedCommand, DispatchedQuery, Metadata};
+
+type Stub = Box<dyn Fn(&DispatchedQuery) + Send + Sync>;
+
+struct Recorded {
+    name: String,
+    message: Option<Box<dyn Any + Send + Sync>>,
+    metadata: Metadata,
+}
+
+/// A command or query recorded by a [`RecordingBus`]
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct RecordedMessage {
+    /// The type name of the message
+    pub name: String,
+    /// The metadata the message was dispatched with
+    pub metadata: Metadata,
+}
+
+/// Stores what was dispatched through a recording bus
+#[derive(Default)]
+pub(crate) struct Recorder {
+    commands: Mutex<Vec<Recorded>>,
+    queries: Mutex<Vec<Recorded>>,
+    stubs: Mutex<HashMap<&'static str, Stub>>,
+}
+
+impl Recorder {
+    pub(crate) fn record_command(&self, mut dispatched: DispatchedCommand) -> DispatchedCommand {
+        tracing::debug!(target: "bus_stop", "recorded command: {:?}", dispatched.name());
+        dispatched.handled = true;
+        self.commands.lock().unwrap().push(Recorded {
+            name: dispatched.name().to_string(),
+            message: dispatched.take_boxed(),
+            metadata: dispatched.metadata().clone(),
+        });
+
+        dispatched
+    }
+
+    pub(crate) fn record_query(&self, mut dispatched: DispatchedQuery) -> DispatchedQuery {
+        tracing::debug!(target: "bus_stop", "recorded query: {:?}", dispatched.name());
+        if let Some(stub) = self.stubs.lock().unwrap().get(dispatched.name()) {
+            stub(&dispatched);
+            dispatched.handled = true;
+        }
+
+        self.queries.lock().unwrap().push(Recorded {
+            name: dispatched.name().to_string(),
+            message: dispatched.take_boxed(),
+            metadata: dispatched.metadata().clone(),
+        });
+
+        dispatched
+    }
+}
+
+/// An isolated bus that records what is dispatched through it
+///
+/// Commands are recorded and reported as handled. Queries are recorded
+/// and answered by their stub, if one was registered. Handlers and
+/// middlewares registered on the bus are never called.
+pub struct RecordingBus {
+    bus: Arc<Busstop>,
+    recorder: Arc<Recorder>,
+}
+
+impl RecordingBus {
+    /// Create a new instance
+    pub fn new() -> Self {
+        let recorder = Arc::new(Recorder::default());
+        let mut bus = Busstop::new();
+        bus.recorder = Some(Arc::clone(&recorder));
+
+        Self {
+            bus: Arc::new(bus),
+            recorder,
+        }
+    }
+
+    /// The bus to hand to the code under test
+    pub fn bus(&self) -> Arc<Busstop> {
+        Arc::clone(&self.bus)
+    }
+
+    /// Answers every dispatch of `Q` with a clone of the value
+    pub fn stub_query<Q: 'static, V: Clone + Send + Sync + 'static>(&self, value: V) -> &Self {
+        self.stub_query_with::<Q, _, _>(move |_| value.clone())
+    }
+
+    /// Answers every dispatch of `Q` with the value returned by the function
+    pub fn stub_query_with<Q, V, F>(&self, answer: F) -> &Self
+    where
+        Q: 'static,
+        V: Send + Sync + 'static,
+        F: Fn(&Q) -> V + Send + Sync + 'static,
+    {
+        self.recorder.stubs.lock().unwrap().insert(
+            std::any::type_name::<Q>(),
+            Box::new(move |dispatched: &DispatchedQuery| {
+                if let Some(query) = dispatched.the_query::<Q>() {
+                    dispatched.set_value(answer(query));
+                }
+            }),
+        );
+
+        self
+    }
+
+    /// Every command dispatched so far, in order
+    pub fn dispatched_commands(&self) -> Vec<RecordedMessage> {
+        Self::messages(&self.recorder.commands)
+    }
+
+    /// Every query dispatched so far, in order
+    pub fn dispatched_queries(&self) -> Vec<RecordedMessage> {
+        Self::messages(&self.recorder.queries)
+    }
+
+    /// The number of times `C` was dispatched and matched the predicate
+    pub fn count_dispatched<C: 'static>(&self, predicate: impl Fn(&C) -> bool) -> usize {
+        Self::count(&self.recorder.commands, predicate)
+    }
+
+    /// The number of times `Q` was dispatched and matched the predicate
+    pub fn count_queried<Q: 'static>(&self, predicate: impl Fn(&Q) -> bool) -> usize {
+        Self::count(&self.recorder.queries, predicate)
+    }
+
+    /// Panics unless a command of type `C` matching the predicate was dispatched
+    #[track_caller]
+    pub fn assert_dispatched<C: 'static>(&self, predicate: impl Fn(&C) -> bool) {
+        if self.count_dispatched(predicate) == 0 {
+            panic!(
+                "expected command {} to be dispatched. Dispatched commands: {:?}",
+                std::any::type_name::<C>(),
+                Self::names(&self.recorder.commands)
+            );
+        }
+    }
+
+    /// Panics if a command of type `C` was dispatched
+    #[track_caller]
+    pub fn assert_not_dispatched<C: 'static>(&self) {
+        let count = self.count_dispatched::<C>(|_| true);
+        if count > 0 {
+            panic!(
+                "expected command {} not to be dispatched, it was dispatched {} time(s)",
+                std::any::type_name::<C>(),
+                count
+            );
+        }
+    }
+
+    /// Panics unless a query of type `Q` matching the predicate was dispatched
+    #[track_caller]
+    pub fn assert_queried<Q: 'static>(&self, predicate: impl Fn(&Q) -> bool) {
+        if self.count_queried(predicate) == 0 {
+            panic!(
+                "expected query {} to be dispatched. Dispatched queries: {:?}",
+                std::any::type_name::<Q>(),
+                Self::names(&self.recorder.queries)
+            );
+        }
+    }
+
+    /// Panics if a query of type `Q` was dispatched
+    #[track_caller]
+    pub fn assert_not_queried<Q: 'static>(&self) {
+        let count = self.count_queried::<Q>(|_| true);
+        if count > 0 {
+            panic!(
+                "expected query {} not to be dispatched, it was dispatched {} time(s)",
+                std::any::type_name::<Q>(),
+                count
+            );
+        }
+    }
+
+    /// Forgets everything recorded so far. Stubs are kept
+    pub fn clear(&self) {
+        self.recorder.commands.lock().unwrap().clear();
+        self.recorder.queries.lock().unwrap().clear();
+    }
+
+    fn messages(list: &Mutex<Vec<Recorded>>) -> Vec<RecordedMessage> {
+        list.lock()
+            .unwrap()
+            .iter()
+            .map(|r| RecordedMessage {
+                name: r.name.clone(),
+                metadata: r.metadata.clone(),
+            })
+            .collect()
+    }
+
+    fn count<M: 'static>(list: &Mutex<Vec<Recorded>>, predicate: impl Fn(&M) -> bool) -> usize {
+        list.lock()
+            .unwrap()
+            .iter()
+            .filter_map(|r| r.message.as_ref()?.downcast_ref::<M>())
+            .filter(|m| predicate(m))
+            .count()
+    }
+
+    fn names(list: &Mutex<Vec<Recorded>>) -> Vec<String> {
+        list.lock()
+            .unwrap()
+            .iter()
+            .map(|r| r.name.clone())
+            .collect()
+    }
+}
+
+impl Default for RecordingBus {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    struct CreateUser {
+        email: String,
+    }
+
+    struct DeleteUser;
+
+    struct FindUser(u32);
+
+    #[tokio::test]
+    async fn test_records_commands_with_metadata() {
+        let recording = RecordingBus::new();
+        let bus = recording.bus();
+
+        let handled = bus
+            .dispatch_command_with_metadata(
+                CreateUser {
+                    email: "ada@example.com".to_string(),
+                },
+                Metadata::from_iter([("user", "admin")]),
+            )
+            .await;
+
+        assert!(handled);
+        recording.assert_dispatched::<CreateUser>(|c| c.email == "ada@example.com");
+        recording.assert_not_dispatched::<DeleteUser>();
+        assert_eq!(
+            recording.count_dispatched::<CreateUser>(|c| c.email.is_empty()),
+            0
+        );
+
+        let commands = recording.dispatched_commands();
+        assert_eq!(commands.len(), 1);
+        assert_eq!(commands[0].metadata.get("user"), Some("admin"));
+
+        recording.clear();
+        recording.assert_not_dispatched::<CreateUser>();
+    }
+
+    #[tokio::test]
+    async fn test_stubbed_queries() {
+        let recording = RecordingBus::new();
+        recording.stub_query_with::<FindUser, _, _>(|q| format!("user {}", q.0));
+
+        let result = recording.bus().dispatch_query(FindUser(7)).await;
+        assert_eq!(result.value::<String>().map(String::as_str), Some("user 7"));
+        assert!(result.handled());
+        recording.assert_queried::<FindUser>(|q| q.0 == 7);
+
+        let recording = RecordingBus::new();
+        let result = recording.bus().dispatch_query(FindUser(7)).await;
+        assert!(!result.handled());
+        assert!(result.value::<String>().is_none());
+    }
+
+    #[test]
+    #[should_panic(expected = "expected command")]
+    fn test_assert_dispatched_panics() {
+        RecordingBus::new().assert_dispatched::<DeleteUser>(|_| true);
+    }
+}
@@ -0,0 +1,149 @@
+mod loopback;
+mod remote;
+#[cfg(all(feature = "unix", unix))]
+mod unix;
+
+use std::fmt::Display;
+
+pub use loopback::LoopbackTransport;
+pub use remote::{RemoteCommandHandler, RemoteQueryHandler};
+#[cfg(all(feature = "unix", unix))]
+pub use unix::UnixTransport;
+
+use crate::{CodecError, Envelope};
+
+/// Sends envelopes to another bus and waits for the reply
+#[async_trait::async_trait]
+pub trait Transport: Send + Sync {
+    /// Sends the envelope and returns the reply
+    async fn send(&self, envelope: Envelope) -> Result<Envelope, TransportError>;
+}
+
+#[async_trait::async_trait]
+impl<T: Transport + ?Sized> Transport for std::sync::Arc<T> {
+    async fn send(&self, envelope: Envelope) -> Result<Envelope, TransportError> {
+        self.as_ref().send(envelope).await
+    }
+}
+
+/// Error returned when an envelope could not be delivered
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum TransportError {
+    /// The connection to the other bus is closed
+    Disconnected,
+    /// The reply did not arrive in time
+    TimedOut,
+    /// The envelope could not be (de)serialized
+    Codec(CodecError),
+    /// Any other failure
+    Other(String),
+}
+
+impl Display for TransportError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::Disconnected => write!(f, "transport is disconnected"),
+            Self::TimedOut => write!(f, "transport timed out waiting for the reply"),
+            Self::Codec(e) => e.fmt(f),
+            Self::Other(reason) => write!(f, "transport error: {}", reason),
+        }
+    }
+}
+
+impl std::error::Error for TransportError {}
+
+impl From<CodecError> for TransportError {
+    fn from(value: CodecError) -> Self {
+        Self::Codec(value)
+    }
+}
+
+#[cfg(all(test, feature = "json"))]
+mod test {
+    use std::sync::Arc;
+
+    use serde::{Deserialize, Serialize};
+
+    use super::*;
+    use crate::{Busstop, DispatchError, Metadata, SerializableMessage};
+
+    #[derive(Serialize, Deserialize)]
+    struct ShipOrder {
+        order: u32,
+    }
+    impl SerializableMessage for ShipOrder {}
+
+    #[derive(Serialize, Deserialize)]
+    struct OrderTotal {
+        order: u32,
+    }
+    impl SerializableMessage for OrderTotal {}
+
+    #[derive(Serialize, Deserialize)]
+    struct CancelOrder;
+    impl SerializableMessage for CancelOrder {}
+
+    async fn server() -> Arc<Busstop> {
+        let server = Arc::new(Busstop::new());
+        server
+            .register_command_middleware::<ShipOrder, _>(|c, n| {
+                Box::pin(async move {
+                    assert_eq!(c.metadata().get("user"), Some("james"));
+                    n.call(c).await
+                })
+            })
+            .await
+            .register_command_fn::<ShipOrder, _>(|_: ShipOrder| async {})
+            .await
+            .register_query_fn::<OrderTotal, _>(|q: &OrderTotal| {
+                let total = q.order * 10;
+                async move { total }
+            })
+            .await
+            .register_dynamic_command::<ShipOrder>()
+            .await
+            .register_dynamic_command::<CancelOrder>()
+            .await
+            .register_dynamic_query::<OrderTotal, u32>()
+            .await;
+
+        server
+    }
+
+    #[tokio::test]
+    async fn test_remote_handlers_over_loopback() {
+        let transport = Arc::new(LoopbackTransport::new(server().await));
+        let client = Busstop::new();
+        client
+            .register_command::<ShipOrder>(RemoteCommandHandler::<ShipOrder>::new(
+                transport.clone(),
+            ))
+            .await
+            .register_command::<CancelOrder>(RemoteCommandHandler::<CancelOrder>::new(
+                transport.clone(),
+            ))
+            .await
+            .register_query::<OrderTotal>(RemoteQueryHandler::<OrderTotal, u32>::new(transport))
+            .await;
+
+        let handled = client
+            .dispatch_command_with_metadata(
+                ShipOrder { order: 1 },
+                Metadata::from_iter([("user", "james")]),
+            )
+            .await;
+        assert!(handled);
+
+        let total = client.dispatch_query(OrderTotal { order: 4 }).await;
+        assert_eq!(total.value::<u32>(), Some(&40));
+
+        let cancelled = client
+            .route_command(crate::DispatchedCommand::new(
+                Box::new(CancelOrder),
+                std::any::type_name::<CancelOrder>(),
+            ))
+            .await;
+        assert!(!cancelled.succeeded());
+        assert!(matches!(cancelled.error(), Some(DispatchError::Failed(_))));
+    }
+}
@@ -0,0 +1,45 @@
+use std::sync::Arc;
+
+use super::{Transport, TransportError};
+use crate::{Busstop, Codecs, Envelope};
+
+/// Delivers envelopes to a bus in the same process
+///
+/// Envelopes are still serialized and deserialized on the way in and out,
+/// which makes this transport useful for testing remote handlers
+pub struct LoopbackTransport {
+    bus: Arc<Busstop>,
+    codecs: Codecs,
+}
+
+impl LoopbackTransport {
+    /// Create a new instance that delivers to `bus`
+    pub fn new(bus: Arc<Busstop>) -> Self {
+        Self {
+            bus,
+            codecs: Codecs::default(),
+        }
+    }
+
+    /// Sets the codecs the receiving side supports
+    pub fn with_codecs(mut self, codecs: Codecs) -> Self {
+        self.codecs = codecs;
+        self
+    }
+}
+
+#[async_trait::async_trait]
+impl Transport for LoopbackTransport {
+    async fn send(&self, envelope: Envelope) -> Result<Envelope, TransportError> {
+        let codec = envelope.payload_codec(&self.codecs)?;
+        let envelope = Envelope::from_bytes(&envelope.to_bytes(codec.as_ref())?, &self.codecs)?;
+
+        let reply = self.bus.dispatch_envelope(envelope, &self.codecs).await;
+
+        let codec = reply.payload_codec(&self.codecs)?;
+        Ok(Envelope::from_bytes(
+            &reply.to_bytes(codec.as_ref())?,
+            &self.codecs,
+        )?)
+    }
+}
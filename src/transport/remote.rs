@@ -0,0 +1,166 @@
+use std::{marker::PhantomData, sync::Arc};
+
+use serde::de::DeserializeOwned;
+
+use super::Transport;
+use crate::{
+    Codecs, CommandHandler, DispatchError, DispatchedCommand, DispatchedQuery, Envelope,
+    QueryHandler, SerializableMessage,
+};
+
+const LOG_TARGET: &str = "bus_stop::remote";
+
+/// Forwards the command to another bus over a transport
+///
+/// The remote bus must have the command registered as a dynamic command
+pub struct RemoteCommandHandler<C> {
+    transport: Arc<dyn Transport>,
+    codecs: Codecs,
+    _command: PhantomData<fn(C)>,
+}
+
+impl<C: SerializableMessage> RemoteCommandHandler<C> {
+    /// Create a new instance. Commands are encoded with the preferred codec
+    pub fn new(transport: impl Transport + 'static) -> Self {
+        Self {
+            transport: Arc::new(transport),
+            codecs: Codecs::default(),
+            _command: PhantomData,
+        }
+    }
+
+    /// Sets the codecs used to encode commands and to read replies
+    pub fn with_codecs(mut self, codecs: Codecs) -> Self {
+        self.codecs = codecs;
+        self
+    }
+}
+
+#[async_trait::async_trait]
+impl<C: SerializableMessage> CommandHandler for RemoteCommandHandler<C> {
+    async fn handle_command(&self, mut dispatched: DispatchedCommand) -> DispatchedCommand {
+        let envelope =
+            match build_envelope(&self.codecs, dispatched.the_command::<C>(), |c, codec| {
+                Envelope::for_command(c, codec)
+            }) {
+                Ok(envelope) => envelope.with_metadata(dispatched.metadata().clone()),
+                Err(error) => {
+                    dispatched.fail(error);
+                    return dispatched;
+                }
+            };
+
+        match self.transport.send(envelope).await {
+            Ok(reply) => {
+                if let Some(error) = remote_error(&reply) {
+                    dispatched.fail(error);
+                }
+            }
+            Err(e) => {
+                tracing::error!(target: LOG_TARGET, "could not forward command {}: {}", C::message_name(), e);
+                dispatched.fail(DispatchError::Failed(e.to_string()));
+            }
+        }
+
+        dispatched
+    }
+}
+
+/// Forwards the query to another bus over a transport.
+/// `V` is the type of the value the remote handler sets
+///
+/// The remote bus must have the query registered as a dynamic query
+pub struct RemoteQueryHandler<Q, V> {
+    transport: Arc<dyn Transport>,
+    codecs: Codecs,
+    _query: PhantomData<fn(Q) -> V>,
+}
+
+impl<Q: SerializableMessage, V: DeserializeOwned + Send + Sync + 'static> RemoteQueryHandler<Q, V> {
+    /// Create a new instance. Queries are encoded with the preferred codec
+    pub fn new(transport: impl Transport + 'static) -> Self {
+        Self {
+            transport: Arc::new(transport),
+            codecs: Codecs::default(),
+            _query: PhantomData,
+        }
+    }
+
+    /// Sets the codecs used to encode queries and to read replies
+    pub fn with_codecs(mut self, codecs: Codecs) -> Self {
+        self.codecs = codecs;
+        self
+    }
+}
+
+#[async_trait::async_trait]
+impl<Q, V> QueryHandler for RemoteQueryHandler<Q, V>
+where
+    Q: SerializableMessage,
+    V: DeserializeOwned + Send + Sync + 'static,
+{
+    async fn handle_query(&self, mut dispatched: DispatchedQuery) -> DispatchedQuery {
+        let envelope =
+            match build_envelope(&self.codecs, dispatched.the_query::<Q>(), |q, codec| {
+                Envelope::for_query(q, codec)
+            }) {
+                Ok(envelope) => envelope.with_metadata(dispatched.metadata().clone()),
+                Err(error) => {
+                    dispatched.fail(error);
+                    return dispatched;
+                }
+            };
+
+        let reply = match self.transport.send(envelope).await {
+            Ok(reply) => reply,
+            Err(e) => {
+                tracing::error!(target: LOG_TARGET, "could not forward query {}: {}", Q::message_name(), e);
+                dispatched.fail(DispatchError::Failed(e.to_string()));
+                return dispatched;
+            }
+        };
+
+        if let Some(error) = remote_error(&reply) {
+            dispatched.fail(error);
+        } else if !reply.payload.is_empty() {
+            match reply.decode_payload::<V>(&self.codecs) {
+                Ok(value) => dispatched.set_value(value),
+                Err(e) => dispatched.fail(DispatchError::Failed(e.to_string())),
+            }
+        }
+
+        dispatched
+    }
+}
+
+fn build_envelope<M>(
+    codecs: &Codecs,
+    message: Option<&M>,
+    wrap: impl FnOnce(&M, &dyn crate::Codec) -> Result<Envelope, crate::CodecError>,
+) -> Result<Envelope, DispatchError> {
+    let Some(codec) = codecs.preferred() else {
+        return Err(DispatchError::Failed("no codec available".to_string()));
+    };
+    let Some(message) = message else {
+        return Err(DispatchError::Failed(
+            "the message has already been taken".to_string(),
+        ));
+    };
+
+    wrap(message, codec.as_ref())
+        .map(|envelope| envelope.with_accept(codecs))
+        .map_err(|e| DispatchError::Failed(e.to_string()))
+}
+
+fn remote_error(reply: &Envelope) -> Option<DispatchError> {
+    if let Some(error) = &reply.error {
+        Some(error.clone())
+    } else if !reply.handled {
+        Some(DispatchError::Failed(format!(
+            "{} was not handled by the remote bus",
+            reply.name
+        )))
+    } else {
+        None
+    }
+}
@@ -0,0 +1,431 @@
+use std::{
+    collections::HashMap,
+    io,
+    path::Path,
+    sync::{
+        Arc, Mutex,
+        atomic::{AtomicU64, Ordering},
+    },
+    time::Duration,
+};
+
+use tokio::{
+    io::{AsyncRead, AsyncReadExt, AsyncWrite, AsyncWriteExt},
+    net::{UnixListener, UnixStream, unix::OwnedWriteHalf},
+    sync::{Mutex as AsyncMutex, Semaphore, mpsc, oneshot},
+    task::JoinHandle,
+};
+
+use super::{Transport, TransportError};
+use crate::{Busstop, Codecs, DispatchError, Envelope};
+
+const LOG_TARGET: &str = "bus_stop::unix";
+
+/// The largest frame that will be read from a socket
+const MAX_FRAME_LEN: usize = 16 * 1024 * 1024;
+
+/// The most requests of one connection that are dispatched at the same time.
+/// The connection is not read while that many are waiting for their reply
+const MAX_IN_FLIGHT: usize = 64;
+
+/// Requests waiting for a reply. `None` once the connection is closed
+type Pending = Arc<Mutex<Option<HashMap<u64, oneshot::Sender<Envelope>>>>>;
+
+/// Sends envelopes over a Unix domain socket to a bus started
+/// with `Busstop::serve_unix`
+///
+/// Each envelope is written as a big endian `u32` length followed by
+/// the bytes of the envelope. Requests are given an id so that many
+/// requests can be in flight over the same connection
+pub struct UnixTransport {
+    writer: AsyncMutex<OwnedWriteHalf>,
+    pending: Pending,
+    next_id: AtomicU64,
+    codecs: Codecs,
+    timeout: Option<Duration>,
+    reader: JoinHandle<()>,
+}
+
+impl UnixTransport {
+    /// Connects to the socket at `path`
+    pub async fn connect(path: impl AsRef<Path>) -> io::Result<Self> {
+        Self::connect_with_codecs(path, Codecs::default()).await
+    }
+
+    /// Connects to the socket at `path`. The codecs are used to read the replies
+    pub async fn connect_with_codecs(path: impl AsRef<Path>, codecs: Codecs) -> io::Result<Self> {
+        let (mut read, write) = UnixStream::connect(path).await?.into_split();
+        let pending: Pending = Arc::new(Mutex::new(Some(HashMap::new())));
+
+        let reader = tokio::spawn({
+            let pending = pending.clone();
+            let codecs = codecs.clone();
+            async move {
+                while let Ok(Some(frame)) = read_frame(&mut read).await {
+                    match Envelope::from_bytes(&frame, &codecs) {
+                        Ok(reply) => {
+                            let sender = pending
+                                .lock()
+                                .unwrap()
+                                .as_mut()
+                                .and_then(|p| p.remove(&reply.id));
+                            if let Some(sender) = sender {
+                                _ = sender.send(reply);
+                            }
+                        }
+                        Err(e) => {
+                            tracing::error!(target: LOG_TARGET, "could not read reply: {}", e)
+                        }
+                    }
+                }
+
+                // Dropping the senders wakes up every request still waiting
+                pending.lock().unwrap().take();
+            }
+        });
+
+        Ok(Self {
+            writer: AsyncMutex::new(write),
+            pending,
+            next_id: AtomicU64::new(1),
+            codecs,
+            timeout: None,
+            reader,
+        })
+    }
+
+    /// Sets how long `send` waits for the reply. By default it waits
+    /// until the connection is closed
+    pub fn with_timeout(mut self, timeout: Duration) -> Self {
+        self.timeout = Some(timeout);
+        self
+    }
+}
+
+#[async_trait::async_trait]
+impl Transport for UnixTransport {
+    async fn send(&self, envelope: Envelope) -> Result<Envelope, TransportError> {
+        let id = self.next_id.fetch_add(1, Ordering::Relaxed);
+        let envelope = envelope.with_id(id);
+        let codec = envelope.payload_codec(&self.codecs)?;
+        let bytes = envelope.to_bytes(codec.as_ref())?;
+
+        let (tx, rx) = oneshot::channel();
+        match self.pending.lock().unwrap().as_mut() {
+            Some(pending) => pending.insert(id, tx),
+            None => return Err(TransportError::Disconnected),
+        };
+
+        let written = write_frame(&mut *self.writer.lock().await, &bytes).await;
+        if let Err(e) = written {
+            if let Some(pending) = self.pending.lock().unwrap().as_mut() {
+                pending.remove(&id);
+            }
+            return Err(TransportError::Other(e.to_string()));
+        }
+
+        let reply = match self.timeout {
+            Some(timeout) => match tokio::time::timeout(timeout, rx).await {
+                Ok(reply) => reply,
+                Err(_) => {
+                    if let Some(pending) = self.pending.lock().unwrap().as_mut() {
+                        pending.remove(&id);
+                    }
+                    return Err(TransportError::TimedOut);
+                }
+            },
+            None => rx.await,
+        };
+
+        reply.map_err(|_| TransportError::Disconnected)
+    }
+}
+
+impl Drop for UnixTransport {
+    fn drop(&mut self) {
+        self.reader.abort();
+    }
+}
+
+impl Busstop {
+    /// Listens on the Unix domain socket at `path` and dispatches every
+    /// envelope received through the dynamic dispatch registry
+    ///
+    /// Each connection has at most 64 requests in flight, further requests
+    /// are not read until a reply has been written
+    ///
+    /// The future only completes when accepting a connection fails
+    pub async fn serve_unix(self: Arc<Self>, path: impl AsRef<Path>) -> io::Result<()> {
+        self.serve_unix_listener(UnixListener::bind(path)?, Codecs::default())
+            .await
+    }
+
+    /// Same as `serve_unix` but accepts connections on an existing listener
+    pub async fn serve_unix_listener(
+        self: Arc<Self>,
+        listener: UnixListener,
+        codecs: Codecs,
+    ) -> io::Result<()> {
+        loop {
+            let (stream, _) = listener.accept().await?;
+            let principal = stream
+                .peer_cred()
+                .ok()
+                .map(|credentials| format!("unix:{}", credentials.uid()));
+            tracing::debug!(target: LOG_TARGET, "accepted a connection from {:?}", principal);
+            tokio::spawn(serve_connection(
+                self.clone(),
+                stream,
+                codecs.clone(),
+                principal,
+            ));
+        }
+    }
+}
+
+async fn serve_connection(
+    bus: Arc<Busstop>,
+    stream: UnixStream,
+    codecs: Codecs,
+    principal: Option<String>,
+) {
+    let (mut read, mut write) = stream.into_split();
+    // `None` closes the connection, the client then fails its pending requests
+    let (replies, mut outgoing) = mpsc::channel::<Option<Vec<u8>>>(MAX_IN_FLIGHT);
+    let in_flight = Arc::new(Semaphore::new(MAX_IN_FLIGHT));
+
+    let writer = tokio::spawn(async move {
+        while let Some(Some(frame)) = outgoing.recv().await {
+            if let Err(e) = write_frame(&mut write, &frame).await {
+                tracing::error!(target: LOG_TARGET, "could not write reply: {}", e);
+                break;
+            }
+        }
+    });
+
+    // Once the writer is gone no reply can be sent, so the connection is no longer read
+    loop {
+        let frame = tokio::select! {
+            _ = replies.closed() => break,
+            frame = read_frame(&mut read) => match frame {
+                Ok(Some(frame)) => frame,
+                _ => break,
+            },
+        };
+        let permit = tokio::select! {
+            _ = replies.closed() => break,
+            permit = Arc::clone(&in_flight).acquire_owned() => match permit {
+                Ok(permit) => permit,
+                Err(_) => break,
+            },
+        };
+        let bus = bus.clone();
+        let codecs = codecs.clone();
+        let replies = replies.clone();
+        let principal = principal.clone();
+
+        tokio::spawn(async move {
+            let _permit = permit;
+            let envelope = match Envelope::from_bytes(&frame, &codecs) {
+                Ok(envelope) => envelope,
+                Err(e) => {
+                    // Without the request id the reply cannot be matched
+                    tracing::error!(target: LOG_TARGET, "could not read envelope, closing the connection: {}", e);
+                    _ = replies.send(None).await;
+                    return;
+                }
+            };
+
+            let request = Envelope::reply_to(&envelope, "", Vec::new());
+            let reply = bus.dispatch_envelope_as(envelope, &codecs, principal).await;
+            let bytes = encode_reply(&reply, &codecs).or_else(|e| {
+                tracing::error!(target: LOG_TARGET, "could not write reply: {}", e);
+                let failed = request
+                    .with_handled(reply.handled)
+                    .with_error(DispatchError::Failed(format!(
+                        "reply could not be encoded: {}",
+                        e
+                    )));
+                encode_reply(&failed, &codecs)
+            });
+
+            match bytes {
+                Ok(bytes) => _ = replies.send(Some(bytes)).await,
+                Err(e) => {
+                    tracing::error!(target: LOG_TARGET, "could not write error reply, closing the connection: {}", e);
+                    _ = replies.send(None).await;
+                }
+            }
+        });
+    }
+
+    drop(replies);
+    _ = writer.await;
+    tracing::debug!(target: LOG_TARGET, "connection closed");
+}
+
+/// Encodes the reply with its own codec, or with the preferred codec
+/// when the reply does not carry a payload
+fn encode_reply(reply: &Envelope, codecs: &Codecs) -> Result<Vec<u8>, crate::CodecError> {
+    let codec = match reply.payload_codec(codecs) {
+        Ok(codec) => codec,
+        Err(e) if reply.payload.is_empty() => codecs.preferred().ok_or(e)?,
+        Err(e) => return Err(e),
+    };
+
+    reply.to_bytes(codec.as_ref())
+}
+
+async fn read_frame<R: AsyncRead + Unpin>(reader: &mut R) -> io::Result<Option<Vec<u8>>> {
+    let len = match reader.read_u32().await {
+        Ok(len) => len as usize,
+        Err(e) if e.kind() == io::ErrorKind::UnexpectedEof => return Ok(None),
+        Err(e) => return Err(e),
+    };
+
+    if len > MAX_FRAME_LEN {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidData,
+            format!("frame of {} bytes is too large", len),
+        ));
+    }
+
+    let mut frame = vec![0; len];
+    reader.read_exact(&mut frame).await?;
+
+    Ok(Some(frame))
+}
+
+async fn write_frame<W: AsyncWrite + Unpin>(writer: &mut W, frame: &[u8]) -> io::Result<()> {
+    let Ok(len) = u32::try_from(frame.len()) else {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidInput,
+            "frame is too large",
+        ));
+    };
+
+    writer.write_u32(len).await?;
+    writer.write_all(frame).await?;
+    writer.flush().await
+}
+
+#[cfg(test)]
+mod test {
+    use serde::{Deserialize, Serialize};
+
+    use super::*;
+    use crate::{RemoteQueryHandler, SerializableMessage};
+
+    #[derive(Serialize, Deserialize)]
+    struct Square(u64);
+    impl SerializableMessage for Square {}
+
+    #[tokio::test]
+    async fn test_unix_round_trip() {
+        let path = std::env::temp_dir().join(format!("busstop-{}.sock", std::process::id()));
+        _ = std::fs::remove_file(&path);
+
+        let server = Arc::new(Busstop::new());
+        server
+            .register_query_fn::<Square, _>(|q: &Square| {
+                let n = q.0;
+                async move { n * n }
+            })
+            .await
+            .register_dynamic_query::<Square, u64>()
+            .await;
+        let listener = UnixListener::bind(&path).unwrap();
+        let server = tokio::spawn(server.serve_unix_listener(listener, Codecs::default()));
+
+        let transport = Arc::new(UnixTransport::connect(&path).await.unwrap());
+        let codecs = Codecs::default();
+        let codec = codecs.preferred().unwrap();
+
+        let replies = futures::future::join_all((1..=10).map(|n| {
+            let envelope = Envelope::for_query(&Square(n), codec.as_ref()).unwrap();
+            transport.send(envelope)
+        }))
+        .await;
+
+        for (n, reply) in (1..=10).zip(replies) {
+            let reply = reply.unwrap();
+            assert!(reply.handled);
+            assert_eq!(reply.decode_payload::<u64>(&codecs).unwrap(), n * n);
+        }
+
+        let client = Busstop::new();
+        client
+            .register_query::<Square>(RemoteQueryHandler::<Square, u64>::new(transport))
+            .await;
+        let answer = client.dispatch_query(Square(12)).await;
+        assert_eq!(answer.value::<u64>(), Some(&144));
+
+        server.abort();
+        _ = std::fs::remove_file(&path);
+    }
+
+    #[tokio::test]
+    async fn test_unreadable_envelope_closes_the_connection() {
+        let path = std::env::temp_dir().join(format!("busstop-bad-{}.sock", std::process::id()));
+        _ = std::fs::remove_file(&path);
+
+        let listener = UnixListener::bind(&path).unwrap();
+        let server =
+            tokio::spawn(Arc::new(Busstop::new()).serve_unix_listener(listener, Codecs::default()));
+
+        let mut stream = UnixStream::connect(&path).await.unwrap();
+        write_frame(&mut stream, &[4, b'j', b's', b'o', b'n', b'{'])
+            .await
+            .unwrap();
+        assert!(read_frame(&mut stream).await.unwrap().is_none());
+
+        // The server stops reading too, so writing eventually fails
+        let closed = tokio::time::timeout(Duration::from_secs(5), async {
+            while write_frame(&mut stream, b"{}").await.is_ok() {
+                tokio::time::sleep(Duration::from_millis(10)).await;
+            }
+        })
+        .await;
+        assert!(closed.is_ok());
+
+        server.abort();
+        _ = std::fs::remove_file(&path);
+    }
+
+    #[tokio::test]
+    async fn test_send_times_out() {
+        let path = std::env::temp_dir().join(format!("busstop-slow-{}.sock", std::process::id()));
+        _ = std::fs::remove_file(&path);
+
+        // Accepts the connection and never replies
+        let listener = UnixListener::bind(&path).unwrap();
+        let server = tokio::spawn(async move {
+            let (mut stream, _) = listener.accept().await.unwrap();
+            while let Ok(Some(_)) = read_frame(&mut stream).await {}
+        });
+
+        let transport = UnixTransport::connect(&path)
+            .await
+            .unwrap()
+            .with_timeout(Duration::from_millis(50));
+        let codecs = Codecs::default();
+        let envelope =
+            Envelope::for_query(&Square(2), codecs.preferred().unwrap().as_ref()).unwrap();
+        assert_eq!(
+            transport.send(envelope).await,
+            Err(TransportError::TimedOut)
+        );
+        assert!(
+            transport
+                .pending
+                .lock()
+                .unwrap()
+                .as_ref()
+                .unwrap()
+                .is_empty()
+        );
+
+        server.abort();
+        _ = std::fs::remove_file(&path);
+    }
+}
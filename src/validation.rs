@@ -0,0 +1,231 @@
+use std::fmt::Display;
+
+use futures::future::BoxFuture;
+
+use crate::{
+    Busstop, DispatchError, DispatchedCommand, DispatchedQuery, NextCommandMiddleware,
+    NextQueryMiddleware,
+};
+
+const LOG_TARGET: &str = "bus_stop";
+
+/// A command or query that can check itself before it is handled
+///
+/// Register the validation middleware with `Busstop::register_command_validation`
+/// or `Busstop::register_query_validation`
+pub trait Validate {
+    /// Returns the fields that are not valid
+    fn validate(&self) -> Result<(), ValidationErrors>;
+}
+
+/// A field that did not pass validation
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct FieldError {
+    /// The name of the field
+    pub field: String,
+    /// Why the field is not valid
+    pub message: String,
+}
+
+/// The fields of a message that did not pass validation
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+#[cfg_attr(feature = "serde", serde(transparent))]
+pub struct ValidationErrors {
+    errors: Vec<FieldError>,
+}
+
+impl ValidationErrors {
+    /// Create a new instance
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Adds an error for the field
+    pub fn add(&mut self, field: impl Into<String>, message: impl Into<String>) -> &mut Self {
+        self.errors.push(FieldError {
+            field: field.into(),
+            message: message.into(),
+        });
+        self
+    }
+
+    /// Returns the errors for the field
+    pub fn field(&self, field: &str) -> impl Iterator<Item = &FieldError> {
+        self.errors.iter().filter(move |e| e.field == field)
+    }
+
+    /// Returns an iterator over the errors, in the order they were added
+    pub fn iter(&self) -> impl Iterator<Item = &FieldError> {
+        self.errors.iter()
+    }
+
+    /// The number of errors
+    pub fn len(&self) -> usize {
+        self.errors.len()
+    }
+
+    /// Returns true if there are no errors
+    pub fn is_empty(&self) -> bool {
+        self.errors.is_empty()
+    }
+
+    /// Returns `Ok(())` when there are no errors
+    pub fn into_result(self) -> Result<(), Self> {
+        if self.is_empty() { Ok(()) } else { Err(self) }
+    }
+}
+
+impl Display for ValidationErrors {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        for (index, error) in self.errors.iter().enumerate() {
+            if index > 0 {
+                write!(f, ", ")?;
+            }
+            write!(f, "{}: {}", error.field, error.message)?;
+        }
+
+        Ok(())
+    }
+}
+
+impl std::error::Error for ValidationErrors {}
+
+impl<F: Into<String>, M: Into<String>> FromIterator<(F, M)> for ValidationErrors {
+    fn from_iter<T: IntoIterator<Item = (F, M)>>(iter: T) -> Self {
+        Self {
+            errors: iter
+                .into_iter()
+                .map(|(field, message)| FieldError {
+                    field: field.into(),
+                    message: message.into(),
+                })
+                .collect(),
+        }
+    }
+}
+
+/// Middleware that validates the command before the handler is called.
+/// The command fails with `DispatchError::Validation` when it is not valid
+pub fn validate_command<C: Validate + 'static>(
+    mut dispatched: DispatchedCommand,
+    next: NextCommandMiddleware,
+) -> BoxFuture<'static, DispatchedCommand> {
+    let result = dispatched.the_command::<C>().map(Validate::validate);
+
+    Box::pin(async move {
+        if let Some(Err(errors)) = result {
+            tracing::debug!(target: LOG_TARGET, "command {:?} is not valid: {}", dispatched.name(), errors);
+            dispatched.fail(DispatchError::Validation(errors));
+            return dispatched;
+        }
+
+        next.call(dispatched).await
+    })
+}
+
+/// Middleware that validates the query before the handler is called.
+/// The query fails with `DispatchError::Validation` when it is not valid
+pub fn validate_query<Q: Validate + 'static>(
+    mut dispatched: DispatchedQuery,
+    next: NextQueryMiddleware,
+) -> BoxFuture<'static, DispatchedQuery> {
+    let result = dispatched.the_query::<Q>().map(Validate::validate);
+
+    Box::pin(async move {
+        if let Some(Err(errors)) = result {
+            tracing::debug!(target: LOG_TARGET, "query {:?} is not valid: {}", dispatched.name(), errors);
+            dispatched.fail(DispatchError::Validation(errors));
+            return dispatched;
+        }
+
+        next.call(dispatched).await
+    })
+}
+
+impl Busstop {
+    /// Validates the command before it reaches the handler
+    pub async fn register_command_validation<C: Validate + 'static>(&self) -> &Self {
+        self.register_command_middleware::<C, _>(validate_command::<C>)
+            .await
+    }
+
+    /// Validates the query before it reaches the handler
+    pub async fn register_query_validation<Q: Validate + 'static>(&self) -> &Self {
+        self.register_query_middleware::<Q, _>(validate_query::<Q>)
+            .await
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::sync::{
+        Arc,
+        atomic::{AtomicBool, Ordering},
+    };
+
+    use super::*;
+
+    struct CreateUser {
+        email: String,
+        age: u8,
+    }
+
+    impl Validate for CreateUser {
+        fn validate(&self) -> Result<(), ValidationErrors> {
+            let mut errors = ValidationErrors::new();
+            if !self.email.contains('@') {
+                errors.add("email", "must be an email address");
+            }
+            if self.age < 18 {
+                errors.add("age", "must be at least 18");
+            }
+            errors.into_result()
+        }
+    }
+
+    #[tokio::test]
+    async fn test_invalid_command_is_not_handled() {
+        let called = Arc::new(AtomicBool::new(false));
+        let bus = Busstop::new();
+        let flag = called.clone();
+        bus.register_command_validation::<CreateUser>()
+            .await
+            .register_command_fn::<CreateUser, _>(move |_: CreateUser| {
+                let flag = flag.clone();
+                async move { flag.store(true, Ordering::SeqCst) }
+            })
+            .await;
+
+        let result = bus
+            .route_command(DispatchedCommand::new(
+                Box::new(CreateUser {
+                    email: "ada".to_string(),
+                    age: 12,
+                }),
+                std::any::type_name::<CreateUser>(),
+            ))
+            .await;
+
+        let Some(DispatchError::Validation(errors)) = result.error() else {
+            panic!("expected validation errors, got {:?}", result.error());
+        };
+        assert_eq!(errors.len(), 2);
+        assert_eq!(errors.field("age").count(), 1);
+        assert_eq!(
+            errors.to_string(),
+            "email: must be an email address, age: must be at least 18"
+        );
+        assert!(!called.load(Ordering::SeqCst));
+
+        let handled = bus
+            .dispatch_command(CreateUser {
+                email: "ada@example.com".to_string(),
+                age: 36,
+            })
+            .await;
+        assert!(handled);
+        assert!(called.load(Ordering::SeqCst));
+    }
+}
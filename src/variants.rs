@@ -0,0 +1,360 @@
+use std::{any::TypeId, collections::HashMap, sync::Arc};
+
+use crate::{Busstop, CommandHandler, DispatchedCommand, DispatchedQuery, QueryHandler};
+
+/// The unrouted variants of the messages registered with
+/// `register_command_variants` and `register_query_variants`
+#[derive(Default)]
+pub(crate) struct UnroutedVariants {
+    pub(crate) commands: HashMap<TypeId, Vec<&'static str>>,
+    pub(crate) queries: HashMap<TypeId, Vec<&'static str>>,
+}
+
+struct Route<M, H: ?Sized> {
+    variant: &'static str,
+    matches: fn(&M) -> bool,
+    handler: Arc<H>,
+}
+
+/// Routes the variants of an enum command to separate handlers
+///
+/// Build it with [`crate::command_variants!`], which checks at compile
+/// time that every variant is either routed or listed as unrouted.
+/// Unrouted variants are declined
+pub struct CommandVariants<C> {
+    routes: Vec<Route<C, dyn CommandHandler>>,
+    unrouted: Vec<&'static str>,
+}
+
+impl<C: Send + Sync + 'static> CommandVariants<C> {
+    /// Create a new instance
+    pub fn new() -> Self {
+        Self {
+            routes: Vec::new(),
+            unrouted: Vec::new(),
+        }
+    }
+
+    /// Sends the commands `matches` returns true for to the handler
+    pub fn route(
+        mut self,
+        variant: &'static str,
+        matches: fn(&C) -> bool,
+        handler: impl CommandHandler + 'static,
+    ) -> Self {
+        self.routes.push(Route {
+            variant,
+            matches,
+            handler: Arc::new(handler),
+        });
+        self
+    }
+
+    /// Records a variant that is deliberately not routed
+    pub fn unrouted(mut self, variant: &'static str) -> Self {
+        self.unrouted.push(variant);
+        self
+    }
+
+    /// The variants that are routed, in the order they were added
+    pub fn routed_variants(&self) -> Vec<&'static str> {
+        self.routes.iter().map(|route| route.variant).collect()
+    }
+
+    /// The variants that are not routed
+    pub fn unrouted_variants(&self) -> &[&'static str] {
+        &self.unrouted
+    }
+}
+
+impl<C: Send + Sync + 'static> Default for CommandVariants<C> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+#[async_trait::async_trait]
+impl<C: Send + Sync + 'static> CommandHandler for CommandVariants<C> {
+    async fn handle_command(&self, mut dispatched: DispatchedCommand) -> DispatchedCommand {
+        let route = dispatched
+            .the_command::<C>()
+            .and_then(|command| self.routes.iter().find(|route| (route.matches)(command)));
+
+        match route {
+            Some(route) => route.handler.handle_command(dispatched).await,
+            None => {
+                dispatched.decline();
+                dispatched
+            }
+        }
+    }
+}
+
+/// Routes the variants of an enum query to separate handlers
+///
+/// See [`CommandVariants`]
+pub struct QueryVariants<Q> {
+    routes: Vec<Route<Q, dyn QueryHandler>>,
+    unrouted: Vec<&'static str>,
+}
+
+impl<Q: Send + Sync + 'static> QueryVariants<Q> {
+    /// Create a new instance
+    pub fn new() -> Self {
+        Self {
+            routes: Vec::new(),
+            unrouted: Vec::new(),
+        }
+    }
+
+    /// Sends the queries `matches` returns true for to the handler
+    pub fn route(
+        mut self,
+        variant: &'static str,
+        matches: fn(&Q) -> bool,
+        handler: impl QueryHandler + 'static,
+    ) -> Self {
+        self.routes.push(Route {
+            variant,
+            matches,
+            handler: Arc::new(handler),
+        });
+        self
+    }
+
+    /// Records a variant that is deliberately not routed
+    pub fn unrouted(mut self, variant: &'static str) -> Self {
+        self.unrouted.push(variant);
+        self
+    }
+
+    /// The variants that are routed, in the order they were added
+    pub fn routed_variants(&self) -> Vec<&'static str> {
+        self.routes.iter().map(|route| route.variant).collect()
+    }
+
+    /// The variants that are not routed
+    pub fn unrouted_variants(&self) -> &[&'static str] {
+        &self.unrouted
+    }
+}
+
+impl<Q: Send + Sync + 'static> Default for QueryVariants<Q> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+#[async_trait::async_trait]
+impl<Q: Send + Sync + 'static> QueryHandler for QueryVariants<Q> {
+    async fn handle_query(&self, mut dispatched: DispatchedQuery) -> DispatchedQuery {
+        let route = dispatched
+            .the_query::<Q>()
+            .and_then(|query| self.routes.iter().find(|route| (route.matches)(query)));
+
+        match route {
+            Some(route) => route.handler.handle_query(dispatched).await,
+            None => {
+                dispatched.decline();
+                dispatched
+            }
+        }
+    }
+}
+
+/// Builds a [`CommandVariants`] that routes each variant of an enum
+/// command to its own handler
+///
+/// Every variant must be listed, either with a handler or in the
+/// `unrouted` block, otherwise the code does not compile. Unrouted
+/// variants are declined and reported by `Busstop::introspect`
+///
+/// ```rust
+/// # #![allow(dead_code)]
+/// use busstop::{Busstop, CommandFnHandler, command_variants};
+///
+/// enum Account {
+///     Open { owner: String },
+///     Close(u32),
+///     Freeze(u32),
+/// }
+///
+/// # #[tokio::main]
+/// # async fn main() {
+/// let bus = Busstop::new();
+/// bus.register_command_variants(command_variants!(Account {
+///     Open => CommandFnHandler::new(|_: Account| async {}),
+///     Close => CommandFnHandler::new(|_: Account| async {}),
+/// } unrouted { Freeze }))
+/// .await;
+///
+/// assert!(bus.dispatch_command(Account::Close(7)).await);
+/// assert!(!bus.dispatch_command(Account::Freeze(7)).await);
+/// # }
+/// ```
+///
+/// Leaving out a variant does not compile
+///
+/// ```rust,compile_fail
+/// # #![allow(dead_code)]
+/// use busstop::{CommandFnHandler, command_variants};
+///
+/// enum Account {
+///     Open,
+///     Close,
+/// }
+///
+/// let _ = command_variants!(Account {
+///     Open => CommandFnHandler::new(|_: Account| async {}),
+/// });
+/// ```
+#[macro_export]
+macro_rules! command_variants {
+    ($command:ty { $($variant:ident => $handler:expr),* $(,)? } $(unrouted { $($unrouted:ident),* $(,)? })?) => {{
+        type __Command = $command;
+
+        #[allow(dead_code)]
+        fn __every_variant_is_listed(command: &__Command) {
+            match command {
+                $(__Command::$variant { .. } => (),)*
+                $($(__Command::$unrouted { .. } => (),)*)?
+            }
+        }
+
+        $crate::CommandVariants::<__Command>::new()
+            $(.route(
+                stringify!($variant),
+                |command| matches!(command, __Command::$variant { .. }),
+                $handler,
+            ))*
+            $($(.unrouted(stringify!($unrouted)))*)?
+    }};
+}
+
+/// Builds a [`QueryVariants`] that routes each variant of an enum
+/// query to its own handler
+///
+/// See [`command_variants!`]
+#[macro_export]
+macro_rules! query_variants {
+    ($query:ty { $($variant:ident => $handler:expr),* $(,)? } $(unrouted { $($unrouted:ident),* $(,)? })?) => {{
+        type __Query = $query;
+
+        #[allow(dead_code)]
+        fn __every_variant_is_listed(query: &__Query) {
+            match query {
+                $(__Query::$variant { .. } => (),)*
+                $($(__Query::$unrouted { .. } => (),)*)?
+            }
+        }
+
+        $crate::QueryVariants::<__Query>::new()
+            $(.route(
+                stringify!($variant),
+                |query| matches!(query, __Query::$variant { .. }),
+                $handler,
+            ))*
+            $($(.unrouted(stringify!($unrouted)))*)?
+    }};
+}
+
+impl Busstop {
+    /// Register the routes as the handler of the command and records
+    /// the unrouted variants for `introspect`
+    ///
+    /// Panics like `register_command` when the command already has a handler
+    pub async fn register_command_variants<C: Send + Sync + 'static>(
+        &self,
+        variants: CommandVariants<C>,
+    ) -> &Self {
+        // Held while registering, so that `introspect` does not see the
+        // handler without its unrouted variants
+        let mut unrouted = self.unrouted_variants.write().await;
+        let names = variants.unrouted_variants().to_vec();
+        self.register_command::<C>(variants).await;
+        unrouted.commands.insert(TypeId::of::<C>(), names);
+
+        self
+    }
+
+    /// Register the routes as the handler of the query and records
+    /// the unrouted variants for `introspect`
+    ///
+    /// Panics like `register_query` when the query already has a handler
+    pub async fn register_query_variants<Q: Send + Sync + 'static>(
+        &self,
+        variants: QueryVariants<Q>,
+    ) -> &Self {
+        // See `register_command_variants`
+        let mut unrouted = self.unrouted_variants.write().await;
+        let names = variants.unrouted_variants().to_vec();
+        self.register_query::<Q>(variants).await;
+        unrouted.queries.insert(TypeId::of::<Q>(), names);
+
+        self
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::{CommandFnHandler, QueryFnHandler};
+
+    #[allow(dead_code)]
+    enum Shape {
+        Circle { radius: f64 },
+        Square(f64),
+        Line,
+        Point,
+    }
+
+    #[tokio::test]
+    async fn test_query_variants() {
+        let bus = Busstop::new();
+        bus.register_query_variants(query_variants!(Shape {
+            Circle => QueryFnHandler::new(|s: &Shape| {
+                let area = match s {
+                    Shape::Circle { radius } => 3.0 * radius * radius,
+                    _ => unreachable!(),
+                };
+                async move { area }
+            }),
+            Square => QueryFnHandler::new(|s: &Shape| {
+                let area = match s {
+                    Shape::Square(side) => side * side,
+                    _ => unreachable!(),
+                };
+                async move { area }
+            }),
+        } unrouted { Line, Point }))
+            .await;
+
+        let area = bus.dispatch_query(Shape::Circle { radius: 2.0 }).await;
+        assert_eq!(area.value::<f64>(), Some(&12.0));
+        let area = bus.dispatch_query(Shape::Square(3.0)).await;
+        assert_eq!(area.value::<f64>(), Some(&9.0));
+
+        let line = bus.dispatch_query(Shape::Line).await;
+        assert!(!line.handled());
+        assert!(line.declined());
+    }
+
+    #[tokio::test]
+    async fn test_command_variants() {
+        let bus = Busstop::new();
+        let variants = command_variants!(Shape {
+            Circle => CommandFnHandler::new(|_: Shape| async {}),
+            Square => CommandFnHandler::new(|_: Shape| async {}),
+            Line => CommandFnHandler::new(|_: Shape| async {}),
+            Point => CommandFnHandler::new(|_: Shape| async {}),
+        });
+        assert_eq!(
+            variants.routed_variants(),
+            vec!["Circle", "Square", "Line", "Point"]
+        );
+        assert!(variants.unrouted_variants().is_empty());
+
+        bus.register_command_variants(variants).await;
+        assert!(bus.dispatch_command(Shape::Point).await);
+    }
+}